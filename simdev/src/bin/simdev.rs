@@ -1,7 +1,12 @@
 use clap::Parser;
+use simdev::discovery::run_discover_main;
+use simdev::fleet::run_fleet_main;
+use simdev::mock_edge::run_mock_edge_main;
+use simdev::nostr::run_edge_main;
 use simdev::preludes::*;
 use simdev::report::run_device_main;
 use simdev::report::DeviceContext;
+use simdev::vectors::run_vectors_main;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -12,6 +17,21 @@ async fn main() -> Result<()> {
         .init();
     let cmd = Cmd::parse();
 
+    match &cmd.command {
+        Some(SimdevCommand::Edge(args)) => return run_edge_main(cmd.clone(), args.clone()).await,
+        Some(SimdevCommand::MockEdge(args)) => return run_mock_edge_main(args.clone()).await,
+        Some(SimdevCommand::Decrypt(args)) => return run_decrypt_main(args.clone()),
+        Some(SimdevCommand::Vectors(args)) => return run_vectors_main(args.clone()).await,
+        Some(SimdevCommand::Discover(args)) => {
+            return run_discover_main(cmd.clone(), args.clone()).await
+        }
+        None => {}
+    }
+
+    if cmd.fleet.is_some() || cmd.fleet_manifest.is_some() {
+        return run_fleet_main(cmd).await;
+    }
+
     let ctx = Arc::new(Mutex::new(DeviceContext::default()));
     run_device_main(cmd, ctx, None).await?;
     Ok(())
@@ -7,10 +7,12 @@ use iced::{
     executor, subscription, Alignment, Application, Command, Element, Font, Length, Padding,
     Settings, Subscription, Theme,
 };
+use simdev::health::{node_info_summary, NodeHealthStatus};
 use simdev::preludes::*;
 use simdev::report::run_device_main;
 use simdev::report::DeviceContext;
-use std::collections::VecDeque;
+use simdev::rings::DeviceEvent;
+use std::collections::{HashSet, VecDeque};
 use std::process::exit;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -35,6 +37,9 @@ struct GuiApp {
     cmd: Cmd,
     state: AppState,
     messages: VecDeque<String>,
+    /// DIDs of the connected P2P peers
+    peers: HashSet<String>,
+    node: Option<NodeHealthStatus>,
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +63,8 @@ impl Application for GuiApp {
             cmd,
             state: AppState::Loading,
             messages: VecDeque::new(),
+            peers: HashSet::new(),
+            node: None,
         };
         (app, Command::none())
     }
@@ -93,6 +100,19 @@ impl Application for GuiApp {
                 }
             }
             GuiAppMessage::UpdateWeight(_) => {}
+            GuiAppMessage::DeviceEvent(e) => {
+                match &e {
+                    DeviceEvent::PeerConnected { did } => {
+                        self.peers.insert(did.clone());
+                    }
+                    DeviceEvent::PeerDisconnected { did, .. } => {
+                        self.peers.remove(did);
+                    }
+                    DeviceEvent::PeerStateChanged { .. } => {}
+                }
+                push_message!(e);
+            }
+            GuiAppMessage::NodeHealth(s) => self.node = Some(s),
         }
         Command::none()
     }
@@ -112,11 +132,31 @@ impl Application for GuiApp {
                     .align_items(Alignment::Center)
                     .spacing(10),
                 );
+                let p2p_line = text(format!("P2P peers connected: {}", self.peers.len()))
+                    .font(MONOSPACE)
+                    .size(14);
+                let node_line = text(match &self.node {
+                    None => "Rings node: waiting for NodeInfo".to_string(),
+                    Some(s) => format!(
+                        "Rings node: {} {}",
+                        if s.healthy { "healthy" } else { "unhealthy" },
+                        s.info
+                            .as_ref()
+                            .map(node_info_summary)
+                            .or_else(|| s.last_error.clone())
+                            .unwrap_or_default()
+                    ),
+                })
+                .font(MONOSPACE)
+                .size(14);
                 let messages = Column::with_children(self.messages.iter().map(|m| {
                     let m = m.clone();
                     Text::new(m).font(MONOSPACE).size(14).into()
                 }));
-                container(column![addr_line, horizontal_space(), messages].spacing(5))
+                container(
+                    column![addr_line, p2p_line, node_line, horizontal_space(), messages]
+                        .spacing(5),
+                )
                 // return addr_line.into();/
             }
         };
@@ -0,0 +1,315 @@
+use crate::health::NodeHealth;
+use crate::preludes::*;
+use crate::queue::Backoff;
+use crate::sim::{Clock, SystemClock};
+use futures::channel::mpsc::Sender;
+use futures::SinkExt;
+use rand::rngs::OsRng;
+use rings_node::provider::Provider;
+use rings_rpc::method::Method;
+use rings_rpc::protos::rings_node::*;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::{timeout, Instant};
+
+static BOOTSTRAP_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+static BOOTSTRAP_RETRY_BASE: Duration = Duration::from_secs(1);
+static BOOTSTRAP_RETRY_MAX: Duration = Duration::from_secs(60);
+static BOOTSTRAP_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootstrapState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// Connectivity of one bootstrap node.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BootstrapStatus {
+    pub url: String,
+    /// DID of the node, known once connected
+    pub did: Option<String>,
+    pub state: BootstrapState,
+    /// Failed connection attempts since the last success
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// Unix time in seconds of the last state change
+    pub since: u64,
+}
+
+/// DID of the peer reached by `ConnectPeerViaHttp`.
+fn connected_did(resp: &ConnectPeerViaHttpResponse) -> Option<String> {
+    let did = resp.peer.as_ref()?.did.to_lowercase();
+    (!did.is_empty()).then_some(did)
+}
+
+/// `(did, state)` of the swarm peers listed in a `NodeInfo` response.
+fn node_info_peer_states(info: &NodeInfoResponse) -> Vec<(String, String)> {
+    info.swarm
+        .as_ref()
+        .map(|s| {
+            s.peers
+                .iter()
+                .map(|p| (p.did.to_lowercase(), p.state.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Map a WebRTC connection state, by name, to a bootstrap state.
+fn bootstrap_state_of(state: &str) -> Option<BootstrapState> {
+    match state {
+        "Connected" => Some(BootstrapState::Connected),
+        "New" | "Connecting" => Some(BootstrapState::Connecting),
+        "Disconnected" | "Failed" | "Closed" => Some(BootstrapState::Disconnected),
+        _ => None,
+    }
+}
+
+/// Keeps the node connected to its bootstrap nodes, reconnecting with backoff.
+///
+/// Drops are noticed through swarm connection events, forwarded by the swarm callback to
+/// `on_peer_state`, and through the `NodeInfo` responses polled by `NodeHealth`.
+pub struct BootstrapSupervisor {
+    provider: Arc<Provider>,
+    health: Arc<NodeHealth>,
+    addr: String,
+    peers: Mutex<Vec<BootstrapStatus>>,
+    changed: Notify,
+    tx: Option<Sender<GuiAppMessage>>,
+}
+
+impl BootstrapSupervisor {
+    pub fn new(
+        provider: Arc<Provider>,
+        health: Arc<NodeHealth>,
+        addr: &str,
+        urls: &[String],
+        tx: Option<Sender<GuiAppMessage>>,
+    ) -> Arc<Self> {
+        let peers = urls
+            .iter()
+            .map(|url| BootstrapStatus {
+                url: url.clone(),
+                did: None,
+                state: BootstrapState::Disconnected,
+                failures: 0,
+                last_error: None,
+                since: SystemClock.now(),
+            })
+            .collect();
+        Arc::new(Self {
+            provider,
+            health,
+            addr: addr.to_string(),
+            peers: Mutex::new(peers),
+            changed: Notify::new(),
+            tx,
+        })
+    }
+
+    /// Spawn one supervising task per bootstrap node.
+    pub fn start(self: &Arc<Self>) {
+        let n = self.peers.lock().unwrap().len();
+        for i in 0..n {
+            let s = self.clone();
+            tokio::spawn(async move { s.supervise(i).await });
+        }
+    }
+
+    pub fn status(&self) -> Vec<BootstrapStatus> {
+        self.peers.lock().unwrap().clone()
+    }
+
+    /// Whether at least one bootstrap node is connected.
+    pub fn is_connected(&self) -> bool {
+        self.peers
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|p| p.state == BootstrapState::Connected)
+    }
+
+    /// Record a connection state change of `did`, named after `WebrtcConnectionState`.
+    pub async fn on_peer_state(&self, did: &str, state: &str) {
+        let Some(state) = bootstrap_state_of(state) else {
+            return;
+        };
+        let index = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .position(|p| p.did.as_ref().is_some_and(|d| d.eq_ignore_ascii_case(did)));
+        if let Some(i) = index {
+            self.set_state(i, state, None).await;
+            self.changed.notify_waiters();
+        }
+    }
+
+    async fn set_state(&self, i: usize, state: BootstrapState, error: Option<String>) {
+        let changed = {
+            let mut peers = self.peers.lock().unwrap();
+            let p = &mut peers[i];
+            match state {
+                BootstrapState::Connected => {
+                    p.failures = 0;
+                    p.last_error = None;
+                }
+                BootstrapState::Disconnected if error.is_some() => {
+                    p.failures = p.failures.saturating_add(1);
+                    p.last_error = error.clone();
+                }
+                _ => {}
+            }
+            if p.state == state {
+                None
+            } else {
+                p.state = state;
+                p.since = SystemClock.now();
+                Some(p.url.clone())
+            }
+        };
+        let Some(url) = changed else {
+            return;
+        };
+        let m = match &error {
+            Some(e) => format!("Bootstrap node {} {:?}: {}", url, state, e),
+            None => format!("Bootstrap node {} {:?}", url, state),
+        };
+        match state {
+            BootstrapState::Disconnected => warn!("[{}] {}", &self.addr, &m),
+            _ => info!("[{}] {}", &self.addr, &m),
+        }
+        if let Some(tx) = &self.tx {
+            let _ = tx.clone().send(GuiAppMessage::Message(m)).await;
+        }
+    }
+
+    async fn connect(&self, url: &str) -> Result<Option<String>> {
+        let resp = self
+            .provider
+            .request(
+                Method::ConnectPeerViaHttp,
+                ConnectPeerViaHttpRequest {
+                    url: url.to_string(),
+                },
+            )
+            .await
+            .map_err(|e| anyhow!("{}", e))?;
+        let resp: ConnectPeerViaHttpResponse = serde_json::from_value(resp)?;
+        debug!("[{}] Connecting to {}: {:?}", &self.addr, url, &resp);
+        Ok(connected_did(&resp))
+    }
+
+    /// State of `did`, or of any peer without a DID, according to the latest `NodeInfo`
+    /// polled after `after` (unix seconds). No value when there is no such response.
+    fn check(&self, did: Option<&str>, after: u64) -> Option<BootstrapState> {
+        let status = self.health.status();
+        if status.last_success.map_or(true, |t| t <= after) {
+            return None;
+        }
+        let peers = node_info_peer_states(status.info.as_ref()?);
+        match did {
+            Some(did) => {
+                let did = did.to_lowercase();
+                match peers.into_iter().find(|(d, _)| d == &did) {
+                    Some((_, state)) => bootstrap_state_of(&state),
+                    None => Some(BootstrapState::Disconnected),
+                }
+            }
+            None => Some(match peers.iter().any(|(_, state)| state == "Connected") {
+                true => BootstrapState::Connected,
+                false => BootstrapState::Connecting,
+            }),
+        }
+    }
+
+    async fn supervise(self: Arc<Self>, i: usize) {
+        let url = self.peers.lock().unwrap()[i].url.clone();
+        let mut backoff = Backoff::new(BOOTSTRAP_RETRY_BASE, BOOTSTRAP_RETRY_MAX);
+        loop {
+            self.set_state(i, BootstrapState::Connecting, None).await;
+            match self.connect(&url).await {
+                Ok(did) => {
+                    if did.is_some() {
+                        self.peers.lock().unwrap()[i].did = did;
+                    }
+                    // The WebRTC handshake goes on in the background, the connection
+                    // events or the next check tell how it ended.
+                    if self.wait_for_drop(i).await {
+                        backoff.reset();
+                    }
+                }
+                Err(e) => {
+                    let e = Some(e.to_string());
+                    self.set_state(i, BootstrapState::Disconnected, e).await
+                }
+            }
+            let delay = backoff.fail(&mut OsRng);
+            debug!(
+                "[{}] Reconnecting to bootstrap node {} in {:?}",
+                &self.addr, &url, delay
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Return once bootstrap node `i` has dropped or failed to connect, tells whether it
+    /// was connected in between.
+    async fn wait_for_drop(&self, i: usize) -> bool {
+        let started = SystemClock.now();
+        let mut connected = false;
+        // Since when the node is neither known connected nor known gone
+        let mut pending_since = Instant::now();
+        loop {
+            let notified = self.changed.notified();
+            let _ = timeout(BOOTSTRAP_CHECK_INTERVAL, notified).await;
+
+            let (did, state) = {
+                let peers = self.peers.lock().unwrap();
+                (peers[i].did.clone(), peers[i].state)
+            };
+            if state == BootstrapState::Disconnected {
+                return connected;
+            }
+            // Without a DID any connected peer is taken as the bootstrap node.
+            let checked = self.check(did.as_deref(), started);
+            let error = match checked {
+                Some(BootstrapState::Connected) => {
+                    connected = true;
+                    pending_since = Instant::now();
+                    self.set_state(i, BootstrapState::Connected, None).await;
+                    continue;
+                }
+                // Connected according to swarm events, NodeInfo has not caught up yet
+                None if state == BootstrapState::Connected => {
+                    connected = true;
+                    pending_since = Instant::now();
+                    continue;
+                }
+                Some(BootstrapState::Disconnected) => "peer left the swarm",
+                _ => {
+                    if pending_since.elapsed() < self.handshake_timeout() {
+                        continue;
+                    }
+                    match checked {
+                        None => "connection state unknown, no recent NodeInfo",
+                        _ => "handshake timed out",
+                    }
+                }
+            };
+            self.set_state(i, BootstrapState::Disconnected, Some(error.to_string()))
+                .await;
+            return connected;
+        }
+    }
+
+    /// How long a connection may stay pending, at least two `NodeInfo` polls.
+    fn handshake_timeout(&self) -> Duration {
+        BOOTSTRAP_HANDSHAKE_TIMEOUT.max(self.health.interval() * 2)
+    }
+}
@@ -0,0 +1,644 @@
+use crate::bootstrap::BootstrapStatus;
+use crate::health::NodeHealthStatus;
+use crate::preludes::*;
+use crate::report::DeviceContext;
+use anyhow::ensure;
+use clap::Args;
+use dephy_edge::preludes::DephySessionStore;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+pub static CONTROL_PROTOCOL_VERSION: u32 = 1;
+
+pub static CONTROL_MIN_INTERVAL: u64 = 1;
+pub static CONTROL_MAX_INTERVAL: u64 = 86400;
+
+/// Commands a controller can send to a device.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ControlCommand {
+    SetWeight {
+        weight: f64,
+    },
+    /// Seconds between reports
+    SetInterval {
+        interval: u64,
+    },
+    Pause,
+    Resume,
+    /// Send the following reports on `MessageChannel::Normal(channel)` only
+    SetChannel {
+        channel: u8,
+    },
+    GetStatus,
+    /// Start a new DePHY session, nonces restart with it
+    RotateSession,
+}
+
+/// Envelope of a control command, sent as JSON in a `BackendMessage::PlainText`.
+///
+/// `{"version":1,"command":{"SetWeight":{"weight":2.5}}}`, unit commands are plain
+/// strings: `{"version":1,"command":"Pause"}`. The optional `id` is echoed in the reply.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ControlMessage {
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    pub command: ControlCommand,
+}
+
+impl ControlMessage {
+    pub fn new(command: ControlCommand) -> Self {
+        Self {
+            version: CONTROL_PROTOCOL_VERSION,
+            id: None,
+            command,
+        }
+    }
+
+    pub fn with_id(id: u64, command: ControlCommand) -> Self {
+        Self {
+            id: Some(id),
+            ..Self::new(command)
+        }
+    }
+
+    /// Parse a control message, a bare number is the legacy weight update of the web UI.
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        let msg = match text.parse::<f64>() {
+            Ok(weight) => Self::new(ControlCommand::SetWeight { weight }),
+            Err(_) => serde_json::from_str::<Self>(text)
+                .map_err(|e| anyhow!("Bad control message: {}", e))?,
+        };
+        ensure!(
+            msg.version == CONTROL_PROTOCOL_VERSION,
+            "Unsupported control protocol version {}, expected {}",
+            msg.version,
+            CONTROL_PROTOCOL_VERSION
+        );
+        msg.command.validate()?;
+        Ok(msg)
+    }
+
+    pub fn to_text(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+/// Request id of a control message that may not parse as a whole, so that errors can be
+/// replied to.
+pub fn control_message_id(text: &str) -> Option<u64> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    value.get("id")?.as_u64()
+}
+
+/// Outcome of a control message, sent back to its sender.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ControlReply {
+    /// `id` of the control message, if it had one
+    pub id: Option<u64>,
+    pub ok: bool,
+    /// Outcome on success, reason of the failure otherwise
+    pub message: String,
+    /// Device state after the command, only sent when the command was accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<DeviceStatus>,
+}
+
+impl ControlReply {
+    pub fn into_result(self) -> Result<DeviceStatus> {
+        ensure!(self.ok, "Control command failed: {}", self.message);
+        self.status
+            .ok_or_else(|| anyhow!("Control reply carries no device status."))
+    }
+}
+
+/// A `ControlReply` signed by the device key, whose address is also its Rings DID.
+///
+/// `reply` is the JSON of the `ControlReply`, `signature` is the recoverable signature
+/// of `keccak256(reply)` as `0x` hex of `r || s || v`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SignedControlReply {
+    pub version: u32,
+    pub reply: String,
+    pub signature: String,
+}
+
+impl SignedControlReply {
+    pub fn sign(reply: &ControlReply, key: &SigningKey) -> Result<Self> {
+        let reply = serde_json::to_string(reply)?;
+        Ok(Self {
+            version: CONTROL_PROTOCOL_VERSION,
+            signature: sign_text(key, &reply)?,
+            reply,
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let ret: Self = serde_json::from_str(text.trim())?;
+        ensure!(
+            ret.version == CONTROL_PROTOCOL_VERSION,
+            "Unsupported control protocol version {}, expected {}",
+            ret.version,
+            CONTROL_PROTOCOL_VERSION
+        );
+        Ok(ret)
+    }
+
+    pub fn to_text(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Check that the reply was signed by the key of `did` and decode it.
+    pub fn verify(&self, did: &str) -> Result<ControlReply> {
+        let signer = recover_text_signer(&self.reply, &self.signature)?;
+        ensure!(
+            signer == normalize_did(did),
+            "Reply signed by {} instead of {}",
+            signer,
+            did
+        );
+        Ok(serde_json::from_str(&self.reply)?)
+    }
+}
+
+/// Names of the commands, as used in ACL files.
+pub static CONTROL_COMMANDS: &[&str] = &[
+    "SetWeight",
+    "SetInterval",
+    "Pause",
+    "Resume",
+    "SetChannel",
+    "GetStatus",
+    "RotateSession",
+];
+
+impl ControlCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SetWeight { .. } => "SetWeight",
+            Self::SetInterval { .. } => "SetInterval",
+            Self::Pause => "Pause",
+            Self::Resume => "Resume",
+            Self::SetChannel { .. } => "SetChannel",
+            Self::GetStatus => "GetStatus",
+            Self::RotateSession => "RotateSession",
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Self::SetWeight { weight } => {
+                ensure!(weight.is_finite(), "Weight should be a finite number.")
+            }
+            Self::SetInterval { interval } => ensure!(
+                (CONTROL_MIN_INTERVAL..=CONTROL_MAX_INTERVAL).contains(interval),
+                "Interval should be between {} and {} seconds.",
+                CONTROL_MIN_INTERVAL,
+                CONTROL_MAX_INTERVAL
+            ),
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Remote overrides of the device arguments.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceControl {
+    pub paused: bool,
+    pub interval: Option<u64>,
+    pub channel: Option<u8>,
+    /// Bumped on each `RotateSession`
+    pub session_generation: u64,
+}
+
+/// State reported by `GetStatus`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeviceStatus {
+    pub addr: Option<String>,
+    pub weight: f64,
+    pub paused: bool,
+    pub interval: Option<u64>,
+    pub channel: Option<u8>,
+    pub session_generation: u64,
+    pub reports: u64,
+    pub published: u64,
+    pub publish_errors: u64,
+    pub last_report: Option<f64>,
+    #[serde(default)]
+    pub connected_peers: usize,
+    #[serde(default)]
+    pub bootstrap: Vec<BootstrapStatus>,
+    #[serde(default)]
+    pub node: Option<NodeHealthStatus>,
+}
+
+impl DeviceStatus {
+    pub fn of(ctx: &DeviceContext) -> Self {
+        Self {
+            addr: ctx.addr.clone(),
+            weight: ctx.weight,
+            paused: ctx.control.paused,
+            interval: ctx.control.interval,
+            channel: ctx.control.channel,
+            session_generation: ctx.control.session_generation,
+            reports: ctx.metrics.reports,
+            published: ctx.metrics.published,
+            publish_errors: ctx.metrics.publish_errors,
+            last_report: ctx.metrics.last_report,
+            connected_peers: ctx.metrics.peers.len(),
+            bootstrap: ctx
+                .bootstrap
+                .as_ref()
+                .map(|b| b.status())
+                .unwrap_or_default(),
+            node: ctx.health.as_ref().map(|h| h.status()),
+        }
+    }
+}
+
+/// Apply a validated command and wake the report loop, returns a line describing the
+/// outcome.
+pub fn apply_control_command(ctx: &mut DeviceContext, command: &ControlCommand) -> Result<String> {
+    command.validate()?;
+    let ret = match command {
+        ControlCommand::SetWeight { weight } => {
+            ctx.weight = *weight;
+            format!("Weight changed to {}", weight)
+        }
+        ControlCommand::SetInterval { interval } => {
+            ctx.control.interval = Some(*interval);
+            format!("Interval changed to {}s", interval)
+        }
+        ControlCommand::Pause => {
+            ctx.control.paused = true;
+            "Reports paused".to_string()
+        }
+        ControlCommand::Resume => {
+            ctx.control.paused = false;
+            "Reports resumed".to_string()
+        }
+        ControlCommand::SetChannel { channel } => {
+            ctx.control.channel = Some(*channel);
+            format!("Channel changed to {}", channel)
+        }
+        ControlCommand::GetStatus => {
+            format!("Status: {}", serde_json::to_string(&DeviceStatus::of(ctx))?)
+        }
+        ControlCommand::RotateSession => {
+            ctx.session = DephySessionStore::new();
+            ctx.control.session_generation += 1;
+            format!(
+                "Session rotated, generation {}",
+                ctx.control.session_generation
+            )
+        }
+    };
+    ctx.control_changed.notify_one();
+    Ok(ret)
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ControlArgs {
+    /// Rings DIDs allowed to send any control command
+    #[arg(long, env, value_delimiter = ',')]
+    pub controller: Vec<String>,
+
+    /// JSON file listing controller DIDs and their commands, see `AclEntry` for the fields
+    #[arg(long, env)]
+    pub control_acl: Option<PathBuf>,
+
+    /// Let any Rings peer send any control command, as the web UI needs to set the weight
+    #[arg(long, env, conflicts_with_all = ["controller", "control_acl"])]
+    pub control_open: bool,
+}
+
+impl ControlArgs {
+    /// ACL built from the arguments, closed to every peer when no controller is configured
+    /// unless `--control-open` is set.
+    pub fn acl(&self) -> Result<ControlAcl> {
+        if self.control_open {
+            return Ok(ControlAcl::open());
+        }
+        let mut entries = vec![];
+        for did in self.controller.iter() {
+            entries.push(AclEntry {
+                did: did.clone(),
+                commands: None,
+            });
+        }
+        if let Some(path) = &self.control_acl {
+            entries.extend(load_acl_entries(path)?);
+        }
+        ControlAcl::new(entries)
+    }
+}
+
+/// One controller of an ACL file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AclEntry {
+    /// Rings DID of the controller, `*` matches any peer
+    pub did: String,
+    /// Commands allowed, no value allows all of them
+    pub commands: Option<Vec<String>>,
+}
+
+pub fn load_acl_entries<P: AsRef<Path>>(path: P) -> Result<Vec<AclEntry>> {
+    let path = path.as_ref();
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read control ACL {}: {}", path.display(), e))?;
+    Ok(serde_json::from_str(&data)?)
+}
+
+/// Lowercase `0x` address of a Rings DID.
+pub fn normalize_did(did: &str) -> String {
+    let did = did.trim().to_lowercase();
+    match did.strip_prefix("did:rings:") {
+        Some(did) => did.to_string(),
+        None => did,
+    }
+}
+
+/// Which peers may send which control commands.
+#[derive(Clone, Debug, Default)]
+pub struct ControlAcl {
+    /// No value means any peer may send any command
+    rules: Option<HashMap<String, Option<HashSet<String>>>>,
+}
+
+impl ControlAcl {
+    pub fn open() -> Self {
+        Self { rules: None }
+    }
+
+    pub fn new(entries: Vec<AclEntry>) -> Result<Self> {
+        let mut rules: HashMap<String, Option<HashSet<String>>> = HashMap::new();
+        for entry in entries {
+            let commands = match entry.commands {
+                None => None,
+                Some(commands) => {
+                    for c in commands.iter() {
+                        ensure!(
+                            CONTROL_COMMANDS.contains(&c.as_str()),
+                            "Unknown control command {} for {}",
+                            c,
+                            &entry.did
+                        );
+                    }
+                    Some(commands.into_iter().collect::<HashSet<_>>())
+                }
+            };
+            let rule = rules
+                .entry(normalize_did(&entry.did))
+                .or_insert(Some(HashSet::new()));
+            // Entries of the same DID add up, a full permission wins.
+            *rule = match (rule.take(), commands) {
+                (Some(mut r), Some(c)) => {
+                    r.extend(c);
+                    Some(r)
+                }
+                _ => None,
+            };
+        }
+        Ok(Self { rules: Some(rules) })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.rules.is_none()
+    }
+
+    pub fn check(&self, did: &str, command: &ControlCommand) -> Result<()> {
+        let Some(rules) = &self.rules else {
+            return Ok(());
+        };
+        let allowed = |key: &str| match rules.get(key) {
+            Some(None) => true,
+            Some(Some(commands)) => commands.contains(command.name()),
+            None => false,
+        };
+        ensure!(
+            allowed(&normalize_did(did)) || allowed("*"),
+            "{} is not allowed to send {}",
+            did,
+            command.name()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        let msg = ControlMessage::parse(r#"{"version":1,"command":{"SetWeight":{"weight":2.5}}}"#)
+            .unwrap();
+        assert_eq!(
+            msg,
+            ControlMessage::new(ControlCommand::SetWeight { weight: 2.5 })
+        );
+
+        let msg = ControlMessage::parse(r#" {"version":1,"id":7,"command":"Pause"} "#).unwrap();
+        assert_eq!(msg, ControlMessage::with_id(7, ControlCommand::Pause));
+
+        let msg = ControlMessage::with_id(3, ControlCommand::SetInterval { interval: 30 });
+        assert_eq!(ControlMessage::parse(&msg.to_text().unwrap()).unwrap(), msg);
+    }
+
+    #[test]
+    fn parses_legacy_weight() {
+        let msg = ControlMessage::parse(" 1.5\n").unwrap();
+        assert_eq!(
+            msg,
+            ControlMessage::new(ControlCommand::SetWeight { weight: 1.5 })
+        );
+        assert!(ControlMessage::parse("NaN").is_err());
+        assert!(ControlMessage::parse("inf").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_messages() {
+        for text in [
+            "",
+            "pause",
+            r#"{"version":2,"command":"Pause"}"#,
+            r#"{"command":"Pause"}"#,
+            r#"{"version":1,"command":"Reboot"}"#,
+            r#"{"version":1,"command":"Pause","extra":true}"#,
+            r#"{"version":1,"command":{"SetInterval":{"interval":0}}}"#,
+            r#"{"version":1,"command":{"SetInterval":{"interval":86401}}}"#,
+            r#"{"version":1,"command":{"SetChannel":{"channel":256}}}"#,
+        ] {
+            assert!(ControlMessage::parse(text).is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn finds_id_of_bad_messages() {
+        assert_eq!(
+            control_message_id(r#"{"version":2,"id":5,"command":"Pause"}"#),
+            Some(5)
+        );
+        assert_eq!(
+            control_message_id(r#"{"version":1,"command":"Pause"}"#),
+            None
+        );
+        assert_eq!(control_message_id("1.5"), None);
+    }
+
+    fn reply(ok: bool) -> ControlReply {
+        ControlReply {
+            id: Some(3),
+            ok,
+            message: "Reports paused".to_string(),
+            status: ok.then(|| DeviceStatus::of(&DeviceContext::default())),
+        }
+    }
+
+    #[test]
+    fn signed_replies_round_trip() {
+        let key = random_signing_key();
+        let did = get_eth_address(&key.clone().into());
+        let signed = SignedControlReply::sign(&reply(true), &key).unwrap();
+        let parsed = SignedControlReply::parse(&signed.to_text().unwrap()).unwrap();
+        assert_eq!(parsed, signed);
+        assert_eq!(parsed.verify(&did).unwrap(), reply(true));
+        let upper = format!("did:rings:{}", did.to_uppercase());
+        assert_eq!(parsed.verify(&upper).unwrap(), reply(true));
+
+        let status = parsed.verify(&did).unwrap().into_result().unwrap();
+        assert!(!status.paused);
+        assert!(SignedControlReply::sign(&reply(false), &key)
+            .unwrap()
+            .verify(&did)
+            .unwrap()
+            .into_result()
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_replies_of_other_signers() {
+        let key = random_signing_key();
+        let other = get_eth_address(&random_signing_key().into());
+        let signed = SignedControlReply::sign(&reply(true), &key).unwrap();
+        assert!(signed.verify(&other).is_err());
+
+        let did = get_eth_address(&key.clone().into());
+        let tampered = SignedControlReply {
+            reply: signed.reply.replace("paused", "resumed"),
+            ..signed
+        };
+        assert!(tampered.verify(&did).is_err());
+    }
+
+    static A: &str = "0x00000000000000000000000000000000000000aa";
+    static B: &str = "0x00000000000000000000000000000000000000bb";
+
+    fn entry(did: &str, commands: Option<&[&str]>) -> AclEntry {
+        AclEntry {
+            did: did.to_string(),
+            commands: commands.map(|c| c.iter().map(|c| c.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn acl_is_closed_by_default() {
+        let args = ControlArgs {
+            controller: vec![],
+            control_acl: None,
+            control_open: false,
+        };
+        let acl = args.acl().unwrap();
+        assert!(!acl.is_open());
+        assert!(acl.check(A, &ControlCommand::GetStatus).is_err());
+
+        let acl = ControlArgs {
+            control_open: true,
+            ..args.clone()
+        }
+        .acl()
+        .unwrap();
+        assert!(acl.is_open());
+
+        let acl = ControlArgs {
+            controller: vec![A.to_string()],
+            ..args
+        }
+        .acl()
+        .unwrap();
+        acl.check(A, &ControlCommand::Pause).unwrap();
+        assert!(acl.check(B, &ControlCommand::Pause).is_err());
+    }
+
+    #[test]
+    fn open_acl_allows_anyone() {
+        let acl = ControlAcl::open();
+        assert!(acl.is_open());
+        acl.check(A, &ControlCommand::RotateSession).unwrap();
+    }
+
+    #[test]
+    fn acl_restricts_peers_and_commands() {
+        let acl = ControlAcl::new(vec![
+            entry(A, None),
+            entry(B, Some(&["GetStatus", "Pause"])),
+        ])
+        .unwrap();
+        assert!(!acl.is_open());
+        acl.check(A, &ControlCommand::RotateSession).unwrap();
+        acl.check(B, &ControlCommand::GetStatus).unwrap();
+        acl.check(B, &ControlCommand::Pause).unwrap();
+        assert!(acl.check(B, &ControlCommand::Resume).is_err());
+        assert!(acl
+            .check(
+                "0x00000000000000000000000000000000000000cc",
+                &ControlCommand::GetStatus
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn acl_normalizes_dids() {
+        let upper = format!("did:rings:{}", A.to_uppercase());
+        let acl = ControlAcl::new(vec![entry(&upper, Some(&["Pause"]))]).unwrap();
+        acl.check(A, &ControlCommand::Pause).unwrap();
+        acl.check(&format!(" {} ", A.to_uppercase()), &ControlCommand::Pause)
+            .unwrap();
+    }
+
+    #[test]
+    fn acl_entries_add_up() {
+        let acl = ControlAcl::new(vec![
+            entry(A, Some(&["Pause"])),
+            entry(A, Some(&["Resume"])),
+            entry(B, Some(&["Pause"])),
+            entry(B, None),
+        ])
+        .unwrap();
+        acl.check(A, &ControlCommand::Pause).unwrap();
+        acl.check(A, &ControlCommand::Resume).unwrap();
+        assert!(acl.check(A, &ControlCommand::GetStatus).is_err());
+        acl.check(B, &ControlCommand::RotateSession).unwrap();
+    }
+
+    #[test]
+    fn acl_wildcard_matches_any_peer() {
+        let acl = ControlAcl::new(vec![entry("*", Some(&["GetStatus"])), entry(A, None)]).unwrap();
+        acl.check(B, &ControlCommand::GetStatus).unwrap();
+        assert!(acl.check(B, &ControlCommand::Pause).is_err());
+        acl.check(A, &ControlCommand::Pause).unwrap();
+    }
+
+    #[test]
+    fn acl_rejects_unknown_commands() {
+        assert!(ControlAcl::new(vec![entry(A, Some(&["Reboot"]))]).is_err());
+        assert!(ControlAcl::new(vec![entry(A, Some(&["pause"]))]).is_err());
+    }
+}
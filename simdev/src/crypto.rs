@@ -1,19 +1,21 @@
-use crate::nostr::default_kind;
+use crate::nostr::dephy_event;
 use crate::preludes::*;
+use crate::sim::{Clock, SystemClock};
 use aes::cipher::block_padding::Pkcs7;
-use aes::cipher::{BlockEncryptMut, KeyIvInit};
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
 use anyhow::ensure;
+use clap::{Args, ValueEnum};
 use dephy_types::borsh::{from_slice, to_vec};
 use k256::{
     ecdh::{diffie_hellman, SharedSecret},
     ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey},
     PublicKey, SecretKey,
 };
-use rand::{rngs::OsRng, Fill};
+use rand::{rngs::OsRng, CryptoRng, Fill, RngCore};
 use sha3::{Digest, Keccak256};
-use std::time::{SystemTime, UNIX_EPOCH};
 
 type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
 
 pub fn get_eth_address_bytes(key: &VerifyingKey) -> Bytes {
     let key = key.to_encoded_point(false);
@@ -34,8 +36,18 @@ pub fn parse_signing_key<T: Into<String>>(key_str: T) -> Result<SigningKey> {
     Ok(SigningKey::from_slice(bytes)?)
 }
 
+pub fn parse_public_key<T: Into<String>>(key_str: T) -> Result<PublicKey> {
+    let key_str: String = key_str.into();
+    let bytes = hex::decode(key_str.trim().trim_start_matches("0x"))?;
+    Ok(PublicKey::from_sec1_bytes(bytes.as_slice())?)
+}
+
 pub fn random_signing_key() -> SigningKey {
-    let key = SecretKey::random(&mut OsRng);
+    random_signing_key_with(&mut OsRng)
+}
+
+pub fn random_signing_key_with<R: RngCore + CryptoRng>(rng: &mut R) -> SigningKey {
+    let key = SecretKey::random(rng);
     key.into()
 }
 
@@ -55,10 +67,59 @@ pub fn did_str_to_addr_bytes<T: Into<String>>(did_str: T) -> Result<Vec<u8>> {
     Ok(hex::decode(did_str)?)
 }
 
+/// Parse a 20-byte address given as `0x` hex, bare hex or a `did:dephy:0x` DID.
+pub fn parse_addr_bytes<T: Into<String>>(addr: T) -> Result<Vec<u8>> {
+    let addr: String = addr.into();
+    let addr = addr.trim();
+    if addr.starts_with("did:") {
+        return did_str_to_addr_bytes(addr);
+    }
+    let bytes = hex::decode(addr.trim_start_matches(ETH_ADDRESS_PREFIX))?;
+    ensure!(
+        bytes.len() == 20,
+        "Address should be 20 bytes long: {}",
+        addr
+    );
+    Ok(bytes)
+}
+
+/// Layout of the bytes hashed into `SignedMessage.hash`, which is what gets signed.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashScheme {
+    /// keccak256(raw || session_id || nonce as a decimal string)
+    V1,
+    /// keccak256(raw || session_id || borsh(nonce)), signed by simdev before V1
+    LegacyBorshNonce,
+}
+
+pub static CURRENT_HASH_SCHEME: HashScheme = HashScheme::V1;
+
+impl HashScheme {
+    pub fn hash(&self, raw: &[u8], session_id: &[u8], nonce: u64) -> [u8; 32] {
+        let mut hasher = Keccak256::new();
+        hasher.update(raw);
+        hasher.update(session_id);
+        match self {
+            Self::V1 => hasher.update(nonce.to_string().as_bytes()),
+            // Borsh encodes u64 as 8 little-endian bytes.
+            Self::LegacyBorshNonce => hasher.update(nonce.to_le_bytes()),
+        }
+        hasher.finalize().into()
+    }
+}
+
 pub fn check_message(data: &[u8]) -> Result<(SignedMessage, RawMessage)> {
-    ensure!(data.len() > 0, "Message should not be empty!");
+    let (msg, raw, _) = check_message_with(data, &[CURRENT_HASH_SCHEME])?;
+    Ok((msg, raw))
+}
 
-    let mut hasher = Keccak256::new();
+/// Verify a message hashed with any of `schemes`, returns the scheme that matched.
+pub fn check_message_with(
+    data: &[u8],
+    schemes: &[HashScheme],
+) -> Result<(SignedMessage, RawMessage, HashScheme)> {
+    ensure!(data.len() > 0, "Message should not be empty!");
+    ensure!(!schemes.is_empty(), "No hash scheme accepted!");
 
     let msg = from_slice::<SignedMessage>(data)?;
     let SignedMessage {
@@ -72,17 +133,19 @@ pub fn check_message(data: &[u8]) -> Result<(SignedMessage, RawMessage)> {
     let raw = raw.as_slice();
     let hash = hash.as_slice();
     let hash_hex = hex::encode(hash);
-    hasher.update(raw);
-    hasher.update(session_id.as_slice());
-    hasher.update(nonce.to_string().as_bytes());
-    let curr_hash = hasher.finalize_reset();
-    ensure!(
-        hash == curr_hash.as_slice(),
-        "Hash verification failed: expected=0x{} current=0x{}",
-        hash_hex,
-        hex::encode(curr_hash)
-    );
-    debug!("Raw message hash: 0x{}", hash_hex);
+    let scheme = schemes
+        .iter()
+        .find(|s| hash == s.hash(raw, session_id.as_slice(), nonce).as_slice())
+        .copied();
+    let Some(scheme) = scheme else {
+        bail!(
+            "Hash verification failed: expected=0x{} current=0x{} scheme={:?}",
+            hash_hex,
+            hex::encode(schemes[0].hash(raw, session_id.as_slice(), nonce)),
+            schemes[0]
+        );
+    };
+    debug!("Raw message hash: 0x{} scheme={:?}", hash_hex, scheme);
 
     let raw_msg = from_slice::<RawMessage>(raw)?;
     let RawMessage {
@@ -111,10 +174,7 @@ pub fn check_message(data: &[u8]) -> Result<(SignedMessage, RawMessage)> {
         hex::encode(v),
         from_address_hex,
     );
-    let rs = Signature::try_from(&signature[0..64])?;
-    let v = RecoveryId::try_from(v[0])?;
-    hasher.update(hash);
-    let r_key = VerifyingKey::recover_from_digest(hasher, &rs, v)?;
+    let r_key = recover_message_signer(&msg)?;
     let r_key_addr = get_eth_address_bytes(&r_key);
 
     ensure!(
@@ -137,7 +197,131 @@ pub fn check_message(data: &[u8]) -> Result<(SignedMessage, RawMessage)> {
         }
     );
 
-    Ok((msg, raw_msg))
+    Ok((msg, raw_msg, scheme))
+}
+
+/// Recover the public key that produced the signature of `msg`.
+pub fn recover_message_signer(msg: &SignedMessage) -> Result<VerifyingKey> {
+    let signature = msg.signature.as_slice();
+    ensure!(signature.len() == 65, "Bad signature length!");
+    let rs = Signature::try_from(&signature[0..64])?;
+    let v = RecoveryId::try_from(signature[64])?;
+    let mut hasher = Keccak256::new();
+    hasher.update(msg.hash.as_slice());
+    Ok(VerifyingKey::recover_from_digest(hasher, &rs, v)?)
+}
+
+/// Sign `text` with a recoverable signature, as `0x` hex of `r || s || v`.
+pub fn sign_text(key: &SigningKey, text: &str) -> Result<String> {
+    let (signature, recid) =
+        key.sign_digest_recoverable(Keccak256::new_with_prefix(text.as_bytes()))?;
+    let mut sign_bytes = signature.to_vec();
+    sign_bytes.push(recid.to_byte());
+    Ok(format!("0x{}", hex::encode(sign_bytes)))
+}
+
+/// Address of the key that signed `text` with `sign_text`.
+pub fn recover_text_signer(text: &str, signature: &str) -> Result<String> {
+    let signature = hex::decode(signature.trim_start_matches("0x"))?;
+    ensure!(signature.len() == 65, "Bad signature length!");
+    let rs = Signature::try_from(&signature[0..64])?;
+    let v = RecoveryId::try_from(signature[64])?;
+    let key =
+        VerifyingKey::recover_from_digest(Keccak256::new_with_prefix(text.as_bytes()), &rs, v)?;
+    Ok(get_eth_address(&key))
+}
+
+/// AES-128 key shared by `key` and `peer` through ECDH and HKDF-Keccak256.
+fn shared_aes_key(key: &SigningKey, peer: &PublicKey) -> [u8; 16] {
+    let key = diffie_hellman(key.as_nonzero_scalar(), peer.as_affine());
+    let key = key.extract::<sha3::Keccak256>(None);
+    let ii: [u8; 0] = [];
+    let mut aes_key = [0u8; 16];
+    key.expand(&ii, &mut aes_key).expect("SHARED_KEY.expand");
+    aes_key
+}
+
+/// Decrypt the payload of a message encrypted to `key` by `sender_pubkey`.
+pub fn decrypt_message(
+    key: &SigningKey,
+    raw: &RawMessage,
+    sender_pubkey: &PublicKey,
+) -> Result<Vec<u8>> {
+    ensure!(raw.encrypted, "Message payload is not encrypted.");
+    let recipient = get_eth_address_bytes(key.verifying_key());
+    ensure!(
+        raw.to_address.as_slice() == &recipient,
+        "Message is addressed to 0x{}, not 0x{}",
+        hex::encode(&raw.to_address),
+        hex::encode(&recipient)
+    );
+    let iv = raw
+        .enc_iv
+        .as_ref()
+        .ok_or(anyhow!("Encrypted message has no IV."))?;
+    let aes_key = shared_aes_key(key, sender_pubkey);
+    let cipher = Aes128CbcDec::new_from_slices(&aes_key, iv.as_slice())?;
+    cipher
+        .decrypt_padded_vec_mut::<Pkcs7>(raw.payload.as_slice())
+        .map_err(|e| anyhow!("Failed to decrypt payload: {}", e))
+}
+
+/// Decode a `SignedMessage` given as hex, with or without `0x`, or as bs58.
+pub fn decode_message_str(s: &str) -> Result<Vec<u8>> {
+    let s = s.trim();
+    if let Some(h) = s.strip_prefix("0x") {
+        return Ok(hex::decode(h)?);
+    }
+    if s.len() % 2 == 0 && s.chars().all(|c| c.is_ascii_hexdigit()) {
+        if let Ok(bytes) = hex::decode(s) {
+            return Ok(bytes);
+        }
+    }
+    Ok(bs58::decode(s).into_vec()?)
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct DecryptArgs {
+    /// Recipient private key in hex
+    #[arg(short, long, env = "DECRYPT_KEY")]
+    pub key: String,
+
+    /// Sender public key in SEC1 hex, no value means recovered from the signature
+    #[arg(short, long)]
+    pub sender: Option<String>,
+
+    /// Signed message in hex or bs58
+    pub message: String,
+}
+
+pub fn run_decrypt_main(args: DecryptArgs) -> Result<()> {
+    let key = parse_signing_key(args.key.replace("0x", ""))?;
+    let data = decode_message_str(&args.message)?;
+    let (msg, raw, _) = check_message_with(data.as_slice(), HashScheme::value_variants())?;
+    let sender = match &args.sender {
+        Some(s) => parse_public_key(s)?,
+        None => recover_message_signer(&msg)?.into(),
+    };
+    let payload = decrypt_message(&key, &raw, &sender)?;
+    println!("From: 0x{}", hex::encode(&raw.from_address));
+    println!("To: 0x{}", hex::encode(&raw.to_address));
+    println!("Timestamp: {}", raw.timestamp);
+    println!("Payload: 0x{}", hex::encode(&payload));
+    if let Ok(text) = std::str::from_utf8(&payload) {
+        println!("Payload (UTF-8): {}", text);
+    }
+    Ok(())
+}
+
+/// How `create_message_with` builds a message.
+pub struct MessageOptions<'a> {
+    /// Outer nonce, no value means the timestamp
+    pub nonce: Option<u64>,
+    /// Source of the IV of encrypted payloads
+    pub rng: &'a mut (dyn RngCore + Send),
+    /// Source of the timestamp
+    pub clock: &'a dyn Clock,
+    pub hash_scheme: HashScheme,
 }
 
 #[async_trait::async_trait]
@@ -151,6 +335,16 @@ pub trait DephySigningKey {
         to_address: Option<Vec<u8>>,
         encr_target: Option<PublicKey>,
     ) -> Result<(SignedMessage, RawMessage)>;
+    /// Same as `create_message`, with the nonce, IV, timestamp and hash scheme from `opts`.
+    async fn create_message_with(
+        &self,
+        session_id: Vec<u8>,
+        channel: MessageChannel,
+        payload: Vec<u8>,
+        to_address: Option<Vec<u8>>,
+        encr_target: Option<PublicKey>,
+        opts: MessageOptions<'_>,
+    ) -> Result<(SignedMessage, RawMessage)>;
     async fn create_nostr_event(
         &self,
         session_id: Vec<u8>,
@@ -177,21 +371,47 @@ impl DephySigningKey for SigningKey {
         to_address: Option<Vec<u8>>,
         encr_target: Option<PublicKey>,
     ) -> Result<(SignedMessage, RawMessage)> {
+        self.create_message_with(
+            session_id,
+            channel,
+            payload,
+            to_address,
+            encr_target,
+            MessageOptions {
+                nonce,
+                rng: &mut OsRng,
+                clock: &SystemClock,
+                hash_scheme: CURRENT_HASH_SCHEME,
+            },
+        )
+        .await
+    }
+
+    async fn create_message_with(
+        &self,
+        session_id: Vec<u8>,
+        channel: MessageChannel,
+        payload: Vec<u8>,
+        to_address: Option<Vec<u8>>,
+        encr_target: Option<PublicKey>,
+        opts: MessageOptions<'_>,
+    ) -> Result<(SignedMessage, RawMessage)> {
+        let MessageOptions {
+            nonce,
+            rng,
+            clock,
+            hash_scheme,
+        } = opts;
         let iv = if encr_target.is_some() {
             let mut buf = [0u8; 16];
-            buf.try_fill(&mut OsRng)?;
+            buf.try_fill(rng)?;
             Some(buf.to_vec())
         } else {
             None
         };
         let payload = match &iv {
             Some(iv) => {
-                let key = encr_target.as_ref().unwrap();
-                let key = diffie_hellman(self.as_nonzero_scalar(), key.as_affine());
-                let key = key.extract::<sha3::Keccak256>(None);
-                let ii: [u8; 0] = [];
-                let mut aes_key = [0u8; 16];
-                key.expand(&ii, &mut aes_key).expect("SHARED_KEY.expand");
+                let aes_key = shared_aes_key(self, encr_target.as_ref().unwrap());
                 let cipher = Aes128CbcEnc::new_from_slices(&aes_key, iv.as_slice())?;
                 cipher.encrypt_padded_vec_mut::<Pkcs7>(payload.as_slice())
             }
@@ -207,7 +427,7 @@ impl DephySigningKey for SigningKey {
                 [0u8; 20].into()
             }
         };
-        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let timestamp = clock.now();
         let raw_msg = RawMessage {
             channel,
             timestamp,
@@ -221,12 +441,9 @@ impl DephySigningKey for SigningKey {
 
         let nonce = nonce.unwrap_or(timestamp);
 
+        let raw_hash = hash_scheme.hash(&raw, session_id.as_slice(), nonce);
         let mut hasher = Keccak256::new();
-        hasher.update(&raw);
-        hasher.update(session_id.as_slice());
-        hasher.update(borsh::to_vec(&nonce)?.as_slice());
-        let raw_hash = hasher.finalize_reset();
-        hasher.update(&raw_hash);
+        hasher.update(raw_hash);
         let (signature, recid) = self.sign_digest_recoverable(hasher)?;
         let mut sign_bytes = signature.to_vec();
         sign_bytes.append(&mut vec![recid.to_byte()]);
@@ -257,24 +474,7 @@ impl DephySigningKey for SigningKey {
         let (msg, raw) = self
             .create_message(session_id, nonce, channel, payload, to_address, encr_target)
             .await?;
-        let content = bs58::encode(to_vec(&msg)?.as_slice()).into_string();
-        let tags = vec![
-            Tag::Generic(TagKind::Custom("c".to_string()), vec!["dephy".to_string()]),
-            Tag::Generic(
-                TagKind::Custom("dephy_to".to_string()),
-                vec![format!("did:dephy:0x{}", hex::encode(&raw.to_address))],
-            ),
-            Tag::Generic(
-                TagKind::Custom("dephy_from".to_string()),
-                vec![format!("did:dephy:0x{}", hex::encode(&raw.from_address))],
-            ),
-            Tag::Generic(
-                TagKind::Custom("dephy_edge".to_string()),
-                vec![format!("did:dephy:0x{}", hex::encode(&raw.from_address))],
-            ),
-        ];
-        let ret = EventBuilder::new(default_kind(), content, tags.as_slice()).to_event(keys)?;
-        Ok(ret)
+        dephy_event(&msg, &raw, keys)
     }
 
     fn eth_addr(&self) -> Bytes {
@@ -0,0 +1,151 @@
+use crate::bootstrap::BootstrapSupervisor;
+use crate::health::NodeHealth;
+use crate::preludes::*;
+use crate::rings::{AppRingsProvider, ControlClient};
+use clap::{Args, ValueEnum};
+use rings_node::provider::Provider;
+use rings_rpc::method::Method;
+use rings_rpc::protos::rings_node::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+pub static SERVICE_PREFIX: &str = "dephy-simdev/";
+
+static REGISTRATION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+static LOOKUP_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Args, Clone, Debug)]
+pub struct DiscoverArgs {
+    /// Service to look up, defaults to the one devices started with the same --sensor register
+    #[arg(long)]
+    pub service: Option<String>,
+
+    /// Seconds to wait for the network and for registered devices
+    #[arg(long, default_value_t = 30)]
+    pub discover_timeout: u64,
+}
+
+/// Service a device registers under: `--rings-service`, or `dephy-simdev/<sensor>`.
+pub fn device_service(cmd: &Cmd) -> String {
+    match &cmd.rings.rings_service {
+        Some(name) => name.clone(),
+        None => {
+            let sensor = cmd
+                .sensor
+                .sensor
+                .to_possible_value()
+                .map(|v| v.get_name().to_string())
+                .unwrap_or_default();
+            format!("{}{}", SERVICE_PREFIX, sensor)
+        }
+    }
+}
+
+pub async fn register_service(provider: &Provider, name: &str) -> Result<()> {
+    provider
+        .request(
+            Method::RegisterService,
+            RegisterServiceRequest {
+                name: name.to_string(),
+            },
+        )
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+/// DIDs registered under service `name`.
+pub async fn lookup_service(provider: &Provider, name: &str) -> Result<Vec<String>> {
+    let resp = provider
+        .request(
+            Method::LookupService,
+            LookupServiceRequest {
+                name: name.to_string(),
+            },
+        )
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    let resp: LookupServiceResponse = serde_json::from_value(resp)?;
+    Ok(resp.dids)
+}
+
+/// Register under service `name` whenever the node joins the network, a node without
+/// bootstrap node registers once right away. `addr` prefixes log lines.
+pub fn spawn_service_registration(
+    provider: Arc<Provider>,
+    bootstrap: Arc<BootstrapSupervisor>,
+    addr: String,
+    name: String,
+) {
+    tokio::spawn(async move {
+        let standalone = bootstrap.status().is_empty();
+        let mut registered = false;
+        loop {
+            let connected = standalone || bootstrap.is_connected();
+            if connected && !registered {
+                match register_service(&provider, &name).await {
+                    Ok(_) => {
+                        info!("[{}] Registered as Rings service {}", &addr, &name);
+                        registered = true;
+                        if standalone {
+                            return;
+                        }
+                    }
+                    Err(e) => warn!(
+                        "[{}] Failed to register Rings service {}: {}",
+                        &addr, &name, e
+                    ),
+                }
+            } else if !connected {
+                // Register again after a reconnection, the network may have changed.
+                registered = false;
+            }
+            sleep(REGISTRATION_CHECK_INTERVAL).await;
+        }
+    });
+}
+
+pub async fn run_discover_main(cmd: Cmd, args: DiscoverArgs) -> Result<()> {
+    let service = args.service.clone().unwrap_or_else(|| device_service(&cmd));
+    let deadline = Instant::now() + Duration::from_secs(args.discover_timeout);
+
+    let key = random_signing_key();
+    let addr = get_eth_address(&key.clone().into());
+    let provider = Provider::create(&key, &cmd.rings).await?;
+    let urls = cmd.rings_bootstrap_urls();
+    let health = NodeHealth::new(provider.clone(), &addr, &cmd.rings, None);
+    let bootstrap = BootstrapSupervisor::new(provider.clone(), health.clone(), &addr, &urls, None);
+    let client = Arc::new(ControlClient::new(provider.clone()));
+    provider.clone().init(&bootstrap, &health, client)?;
+
+    while !urls.is_empty() && !bootstrap.is_connected() {
+        if Instant::now() >= deadline {
+            bail!(
+                "Not connected to the Rings network after {}s.",
+                args.discover_timeout
+            );
+        }
+        sleep(LOOKUP_RETRY_INTERVAL).await;
+    }
+    info!("Looking up Rings service {}", &service);
+    // Lookups may come back empty until the DHT has stabilized.
+    let dids = loop {
+        match lookup_service(&provider, &service).await {
+            Ok(dids) if !dids.is_empty() => break dids,
+            Ok(_) => debug!("No DID registered under {} yet", &service),
+            Err(e) => debug!("LookupService: {}", e),
+        }
+        if Instant::now() >= deadline {
+            break vec![];
+        }
+        sleep(LOOKUP_RETRY_INTERVAL).await;
+    };
+    if dids.is_empty() {
+        warn!("No device found under {}", &service);
+    }
+    for did in dids {
+        println!("{}", did);
+    }
+    Ok(())
+}
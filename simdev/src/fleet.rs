@@ -0,0 +1,144 @@
+use crate::preludes::*;
+use crate::report::{run_device_main, DeviceContext};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+
+/// One entry of a fleet manifest, unset fields fall back to the command line or environment.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct FleetDevice {
+    /// Report signer, no value means random signer
+    pub from: Option<String>,
+    pub weight: Option<f64>,
+    /// Send interval in seconds
+    pub interval: Option<u64>,
+    /// Recipient address of reports
+    pub report_to: Option<String>,
+    /// Message channels of reports
+    pub channels: Option<Vec<u8>>,
+}
+
+pub fn load_fleet_manifest<P: AsRef<Path>>(path: P) -> Result<Vec<FleetDevice>> {
+    let path = path.as_ref();
+    let data = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read fleet manifest {}: {}", path.display(), e))?;
+    let devices: Vec<FleetDevice> = serde_json::from_str(&data)?;
+    if devices.is_empty() {
+        bail!("Fleet manifest {} contains no device.", path.display());
+    }
+    Ok(devices)
+}
+
+pub fn fleet_devices(cmd: &Cmd) -> Result<Vec<FleetDevice>> {
+    if let Some(path) = &cmd.fleet_manifest {
+        return load_fleet_manifest(path);
+    }
+    let n = cmd.fleet.unwrap_or(1);
+    if n == 0 {
+        bail!("Fleet size should not be zero.");
+    }
+    if cmd.from.is_some() && n > 1 {
+        warn!("Ignoring --from in fleet mode, every device gets a random signer.");
+    }
+    Ok(vec![FleetDevice::default(); n])
+}
+
+pub async fn run_fleet_main(cmd: Cmd) -> Result<()> {
+    let devices = fleet_devices(&cmd)?;
+    let n = devices.len();
+    info!("Starting fleet of {} devices", n);
+
+    let mut contexts = Vec::with_capacity(n);
+    let mut tasks = JoinSet::new();
+    for (i, device) in devices.into_iter().enumerate() {
+        let mut cmd = cmd.clone();
+        if n > 1 || cmd.fleet_manifest.is_some() {
+            cmd.from = device.from;
+        }
+        if let Some(interval) = device.interval {
+            cmd.interval = interval;
+        }
+        if let Some(report_to) = device.report_to {
+            cmd.report_to = report_to;
+        }
+        if let Some(channels) = device.channels {
+            cmd.channel = channels;
+        }
+        cmd.seed = cmd.seed.map(|seed| seed.wrapping_add(i as u64));
+        if i > 0 {
+            // Only one device can listen on the address, the others bootstrap from it.
+            if let Some(listen) = cmd.rings.rings_listen.take() {
+                cmd.rings_relay_endpoint = format!("http://{}", listen);
+            }
+        }
+        let mut ctx = DeviceContext::default();
+        if let Some(weight) = device.weight {
+            ctx.weight = weight;
+        }
+        let ctx = Arc::new(Mutex::new(ctx));
+        contexts.push(ctx.clone());
+
+        // Spread the first reports over one interval instead of sending them all at once.
+        let delay = Duration::from_millis(cmd.interval * 1000 * i as u64 / n as u64);
+        tasks.spawn(async move {
+            sleep(delay).await;
+            let res = run_device_main(cmd, ctx.clone(), None).await;
+            if let Err(e) = &res {
+                let addr = ctx.lock().await.addr.clone();
+                error!(
+                    "[{}] Device #{} stopped: {}",
+                    addr.unwrap_or_default(),
+                    i,
+                    e
+                );
+            }
+            res
+        });
+    }
+
+    // Outside of the set, which must drain once every device is done, e.g. with --count.
+    let metrics = (cmd.fleet_metrics_interval > 0).then(|| {
+        let report_every = Duration::from_secs(cmd.fleet_metrics_interval);
+        tokio::spawn(async move {
+            loop {
+                sleep(report_every).await;
+                for ctx in contexts.iter() {
+                    let c = ctx.lock().await;
+                    if let Some(addr) = &c.addr {
+                        let m = &c.metrics;
+                        info!(
+                            "[{}] weight={} reports={} published={} publish_errors={} last_report={:?} peers={}",
+                            addr, c.weight, m.reports, m.published, m.publish_errors, m.last_report, m.peers.len()
+                        );
+                    }
+                }
+            }
+        })
+    });
+
+    // A failing device leaves the others running, the fleet fails once they are done.
+    let mut failed = 0;
+    let mut res = Ok(());
+    while let Some(r) = tasks.join_next().await {
+        match r {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => failed += 1,
+            Err(e) => {
+                res = Err(e.into());
+                break;
+            }
+        }
+    }
+    if let Some(metrics) = metrics {
+        metrics.abort();
+    }
+    res?;
+    if failed > 0 {
+        bail!("{} of {} devices failed.", failed, n);
+    }
+    Ok(())
+}
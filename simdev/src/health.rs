@@ -0,0 +1,162 @@
+use crate::preludes::*;
+use crate::rings::RingsArgs;
+use crate::sim::{Clock, SystemClock};
+use futures::channel::mpsc::Sender;
+use futures::SinkExt;
+use rings_node::provider::Provider;
+use rings_rpc::method::Method;
+use rings_rpc::protos::rings_node::*;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Outcome of the latest `NodeInfo` polls.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct NodeHealthStatus {
+    /// False once `--rings-health-failures` polls in a row failed
+    pub healthy: bool,
+    /// Failed polls since the last success
+    pub failures: u32,
+    pub last_error: Option<String>,
+    /// Unix time in seconds of the last successful poll
+    pub last_success: Option<u64>,
+    /// Latest `NodeInfo` response
+    pub info: Option<NodeInfoResponse>,
+}
+
+/// One line description of a `NodeInfo` response: version and peers.
+pub fn node_info_summary(info: &NodeInfoResponse) -> String {
+    let peers = info
+        .swarm
+        .as_ref()
+        .map(|s| s.peers.as_slice())
+        .unwrap_or_default();
+    let connected = peers.iter().filter(|p| p.state == "Connected").count();
+    format!(
+        "version {}, {} peers, {} connected",
+        info.version,
+        peers.len(),
+        connected
+    )
+}
+
+/// Polls `NodeInfo` of the local Rings node and keeps the latest response.
+pub struct NodeHealth {
+    provider: Arc<Provider>,
+    addr: String,
+    interval: Duration,
+    max_failures: u32,
+    status: Mutex<NodeHealthStatus>,
+    tx: Option<Sender<GuiAppMessage>>,
+}
+
+impl NodeHealth {
+    pub fn new(
+        provider: Arc<Provider>,
+        addr: &str,
+        args: &RingsArgs,
+        tx: Option<Sender<GuiAppMessage>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            provider,
+            addr: addr.to_string(),
+            interval: Duration::from_secs(args.rings_health_interval.max(1)),
+            max_failures: args.rings_health_failures.max(1),
+            status: Mutex::new(NodeHealthStatus {
+                healthy: true,
+                ..Default::default()
+            }),
+            tx,
+        })
+    }
+
+    pub fn start(self: &Arc<Self>) {
+        let s = self.clone();
+        tokio::spawn(async move { s.supervise().await });
+    }
+
+    pub fn status(&self) -> NodeHealthStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Time between two polls.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.status.lock().unwrap().healthy
+    }
+
+    /// Latest `NodeInfo` response, if any poll succeeded.
+    pub fn info(&self) -> Option<NodeInfoResponse> {
+        self.status.lock().unwrap().info.clone()
+    }
+
+    async fn poll(&self) -> Result<NodeInfoResponse> {
+        let provider = self.provider.clone();
+        // A panic in the provider ends this request only, not the polling loop.
+        let req = tokio::spawn(async move {
+            provider
+                .request(Method::NodeInfo, NodeInfoRequest {})
+                .await
+                .map_err(|e| anyhow!("{}", e))
+        });
+        match timeout(self.interval, req).await {
+            Ok(Ok(resp)) => Ok(serde_json::from_value(resp?)?),
+            Ok(Err(e)) => bail!("NodeInfo task failed: {}", e),
+            Err(_) => bail!("NodeInfo timed out after {:?}", self.interval),
+        }
+    }
+
+    async fn supervise(self: Arc<Self>) {
+        loop {
+            let resp = self.poll().await;
+            let changed = {
+                let mut s = self.status.lock().unwrap();
+                let healthy = s.healthy;
+                match resp {
+                    Ok(info) => {
+                        debug!("[{}] NodeInfo: {:?}", &self.addr, &info);
+                        s.failures = 0;
+                        s.last_error = None;
+                        s.last_success = Some(SystemClock.now());
+                        s.info = Some(info);
+                    }
+                    Err(e) => {
+                        debug!("[{}] NodeInfo: {}", &self.addr, e);
+                        s.failures = s.failures.saturating_add(1);
+                        s.last_error = Some(e.to_string());
+                    }
+                }
+                s.healthy = s.failures < self.max_failures;
+                (s.healthy != healthy).then(|| s.clone())
+            };
+            if let Some(s) = changed {
+                let m = match s.healthy {
+                    true => "Rings node is healthy again".to_string(),
+                    false => format!(
+                        "Rings node is unhealthy after {} failed NodeInfo polls: {}",
+                        s.failures,
+                        s.last_error.as_deref().unwrap_or_default()
+                    ),
+                };
+                match s.healthy {
+                    true => info!("[{}] {}", &self.addr, &m),
+                    false => warn!("[{}] {}", &self.addr, &m),
+                }
+                if let Some(tx) = &self.tx {
+                    let _ = tx.clone().send(GuiAppMessage::Message(m)).await;
+                }
+            }
+            if let Some(tx) = &self.tx {
+                let _ = tx
+                    .clone()
+                    .send(GuiAppMessage::NodeHealth(self.status()))
+                    .await;
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::preludes::*;
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use rings_node::provider::Provider;
+use rings_rpc::method::Method;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// JSON-RPC call as sent by `ConnectPeerViaHttp` of a peer.
+#[derive(Deserialize, Debug)]
+struct RpcRequest {
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+}
+
+fn rpc_error(id: Value, code: i64, message: String) -> Json<Value> {
+    Json(json!({
+        "jsonrpc": "2.0",
+        "error": { "code": code, "message": message },
+        "id": id,
+    }))
+}
+
+async fn handle_rpc(
+    State(provider): State<Arc<Provider>>,
+    Json(req): Json<RpcRequest>,
+) -> Json<Value> {
+    // Only what a peer needs to connect, the endpoint must not let peers drive this node.
+    let method = match req.method.as_str() {
+        "answerOffer" => Method::AnswerOffer,
+        "nodeInfo" => Method::NodeInfo,
+        m => return rpc_error(req.id, -32601, format!("Method not allowed: {}", m)),
+    };
+    let params = match req.params {
+        Value::Array(mut params) if params.len() == 1 => params.remove(0),
+        params => params,
+    };
+    match provider.request(method, params).await {
+        Ok(result) => Json(json!({ "jsonrpc": "2.0", "result": result, "id": req.id })),
+        Err(e) => {
+            warn!("Rings endpoint {}: {}", &req.method, e);
+            rpc_error(req.id, -32000, e.to_string())
+        }
+    }
+}
+
+/// Serve the subset of the Rings node HTTP API used by `ConnectPeerViaHttp`, so that
+/// peers can bootstrap from this node with `-r http://<listen>` without any relay.
+pub async fn serve_rings_endpoint(
+    provider: Arc<Provider>,
+    listen: SocketAddr,
+) -> Result<SocketAddr> {
+    let app = Router::new()
+        .route("/", post(handle_rpc))
+        .with_state(provider);
+    let server = axum::Server::try_bind(&listen)?.serve(app.into_make_service());
+    let addr = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Rings endpoint: {}", e);
+        }
+    });
+    info!("Rings peers can bootstrap from http://{}", &addr);
+    Ok(addr)
+}
@@ -1,5 +1,20 @@
+pub mod bootstrap;
+pub mod control;
 pub mod crypto;
+pub mod discovery;
+pub mod fleet;
+pub mod health;
+pub mod lan;
+pub mod mock_edge;
+pub mod mqtt;
 pub mod nostr;
 pub mod preludes;
+pub mod queue;
 pub mod report;
 pub mod rings;
+pub mod sensor;
+pub mod sim;
+pub mod status;
+pub mod transport;
+pub mod vectors;
+pub mod verifier;
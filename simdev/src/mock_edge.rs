@@ -0,0 +1,297 @@
+use crate::preludes::*;
+use crate::verifier::{MessageVerifier, VerifierArgs};
+use axum::body::Bytes as BodyBytes;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use borsh::to_vec;
+use clap::Args;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+
+pub static DEPHY_CONTENT_TYPE: &str = "application/x-dephy";
+
+#[derive(Args, Clone, Debug)]
+pub struct MockEdgeArgs {
+    /// Address the mock edge listens on
+    #[arg(long, env, default_value = "127.0.0.1:3883")]
+    pub listen: SocketAddr,
+
+    /// Hashing schemes accepted from devices
+    #[arg(long, env, value_enum, value_delimiter = ',', default_value = "v1")]
+    pub accept_hash_scheme: Vec<HashScheme>,
+
+    /// Also reject stale, future and replayed messages like a real edge
+    #[arg(long, env)]
+    pub verify_freshness: bool,
+
+    #[command(flatten)]
+    pub verifier: VerifierArgs,
+}
+
+/// A message accepted by the mock edge.
+#[derive(Clone, Debug)]
+pub struct AcceptedMessage {
+    pub signed: SignedMessage,
+    pub raw: RawMessage,
+}
+
+/// JSON view of an accepted message served on `GET /dephy/messages`.
+#[derive(Serialize, Clone, Debug)]
+pub struct AcceptedMessageView {
+    pub message: String,
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub timestamp: u64,
+    pub encrypted: bool,
+    pub payload: String,
+}
+
+impl AcceptedMessage {
+    pub fn view(&self) -> Result<AcceptedMessageView> {
+        Ok(AcceptedMessageView {
+            message: format!("0x{}", hex::encode(to_vec(&self.signed)?)),
+            hash: format!("0x{}", hex::encode(&self.signed.hash)),
+            from: format!("0x{}", hex::encode(&self.raw.from_address)),
+            to: format!("0x{}", hex::encode(&self.raw.to_address)),
+            timestamp: self.raw.timestamp,
+            encrypted: self.raw.encrypted,
+            payload: format!("0x{}", hex::encode(&self.raw.payload)),
+        })
+    }
+}
+
+struct MockEdgeState {
+    schemes: Vec<HashScheme>,
+    verifier: Option<Mutex<MessageVerifier>>,
+    messages: Mutex<Vec<AcceptedMessage>>,
+}
+
+async fn handle_signed_message(
+    State(state): State<Arc<MockEdgeState>>,
+    headers: HeaderMap,
+    body: BodyBytes,
+) -> (StatusCode, String) {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    if content_type != DEPHY_CONTENT_TYPE {
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Content type should be {}", DEPHY_CONTENT_TYPE),
+        );
+    }
+    let res = match &state.verifier {
+        Some(verifier) => verifier.lock().await.verify(&body),
+        None => check_message_with(&body, &state.schemes),
+    };
+    match res {
+        Ok((signed, raw, _)) => {
+            info!(
+                "Mock edge accepted message from 0x{}",
+                hex::encode(&raw.from_address)
+            );
+            state
+                .messages
+                .lock()
+                .await
+                .push(AcceptedMessage { signed, raw });
+            (StatusCode::OK, "ok".to_string())
+        }
+        Err(e) => {
+            warn!("Mock edge rejected message: {}", e);
+            (StatusCode::BAD_REQUEST, e.to_string())
+        }
+    }
+}
+
+async fn handle_list_messages(
+    State(state): State<Arc<MockEdgeState>>,
+) -> Result<Json<Vec<AcceptedMessageView>>, (StatusCode, String)> {
+    let messages = state.messages.lock().await;
+    let views = messages
+        .iter()
+        .map(|m| m.view())
+        .collect::<Result<Vec<_>>>()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(views))
+}
+
+/// A running mock edge, stopped when `shutdown` is called or the handle is dropped.
+pub struct MockEdge {
+    pub addr: SocketAddr,
+    state: Arc<MockEdgeState>,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl MockEdge {
+    /// Start serving on `listen`, port 0 picks a free port.
+    pub async fn start(listen: SocketAddr) -> Result<Self> {
+        Self::start_with(listen, vec![CURRENT_HASH_SCHEME], None).await
+    }
+
+    /// Start accepting `schemes`, messages also go through `verifier` when given.
+    pub async fn start_with(
+        listen: SocketAddr,
+        schemes: Vec<HashScheme>,
+        verifier: Option<MessageVerifier>,
+    ) -> Result<Self> {
+        let state = Arc::new(MockEdgeState {
+            schemes,
+            verifier: verifier.map(Mutex::new),
+            messages: Mutex::new(vec![]),
+        });
+        let app = Router::new()
+            .route("/dephy/signed_message", post(handle_signed_message))
+            .route("/dephy/messages", get(handle_list_messages))
+            .with_state(state.clone());
+
+        let server = axum::Server::try_bind(&listen)?.serve(app.into_make_service());
+        let addr = server.local_addr();
+        let (shutdown, rx) = oneshot::channel::<()>();
+        let server = server.with_graceful_shutdown(async move {
+            let _ = rx.await;
+        });
+        let task = tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("Mock edge: {}", e);
+            }
+        });
+        info!("Mock edge listening on http://{}", &addr);
+
+        Ok(Self {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+            task: Some(task),
+        })
+    }
+
+    /// URL to use as `--dephy-http-endpoint`.
+    pub fn endpoint(&self) -> String {
+        format!("http://{}/dephy/signed_message", self.addr)
+    }
+
+    pub async fn messages(&self) -> Vec<AcceptedMessage> {
+        self.state.messages.lock().await.clone()
+    }
+
+    pub async fn clear(&self) {
+        self.state.messages.lock().await.clear();
+    }
+
+    pub async fn shutdown(mut self) -> Result<()> {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+        if let Some(task) = self.task.take() {
+            task.await?;
+        }
+        Ok(())
+    }
+
+    /// Serve until the task stops, used by the `mock-edge` subcommand.
+    pub async fn wait(mut self) -> Result<()> {
+        if let Some(task) = self.task.take() {
+            task.await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MockEdge {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+pub async fn run_mock_edge_main(args: MockEdgeArgs) -> Result<()> {
+    let verifier = args
+        .verify_freshness
+        .then(|| MessageVerifier::new(&args.verifier, args.accept_hash_scheme.clone()));
+    let edge = MockEdge::start_with(args.listen, args.accept_hash_scheme, verifier).await?;
+    info!("Publish to {}", edge.endpoint());
+    edge.wait().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start() -> MockEdge {
+        MockEdge::start("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn signed_message(key: &SigningKey) -> SignedMessage {
+        let (signed, _) = key
+            .create_message(
+                vec![0u8; 16],
+                None,
+                MessageChannel::Normal(0),
+                b"test".to_vec(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        signed
+    }
+
+    async fn post(edge: &MockEdge, content_type: &str, body: Vec<u8>) -> u16 {
+        reqwest::Client::new()
+            .post(edge.endpoint())
+            .header("content-type", content_type)
+            .body(body)
+            .send()
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    }
+
+    #[tokio::test]
+    async fn records_accepted_messages() {
+        let edge = start().await;
+        let key = random_signing_key();
+        let signed = signed_message(&key).await;
+        let status = post(&edge, DEPHY_CONTENT_TYPE, to_vec(&signed).unwrap()).await;
+        assert_eq!(status, 200);
+
+        let messages = edge.messages().await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].signed.hash, signed.hash);
+        assert_eq!(messages[0].signed.signature, signed.signature);
+        assert_eq!(
+            format!("0x{}", hex::encode(&messages[0].raw.from_address)),
+            get_eth_address(&key.clone().into())
+        );
+        assert_eq!(messages[0].raw.payload, b"test");
+        edge.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_requests() {
+        let edge = start().await;
+        let mut signed = signed_message(&random_signing_key()).await;
+        let status = post(&edge, "application/octet-stream", to_vec(&signed).unwrap()).await;
+        assert_eq!(status, 415);
+
+        signed.hash[0] ^= 1;
+        let status = post(&edge, DEPHY_CONTENT_TYPE, to_vec(&signed).unwrap()).await;
+        assert_eq!(status, 400);
+
+        assert!(edge.messages().await.is_empty());
+        edge.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use crate::preludes::*;
+use crate::transport::Transport;
+use async_trait::async_trait;
+use borsh::to_vec;
+use clap::Args;
+use rumqttc::{AsyncClient, Event as MqttEvent, MqttOptions, Packet, QoS};
+use std::time::Duration;
+use tokio::time::sleep;
+
+static MQTT_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Args, Clone, Debug)]
+pub struct MqttArgs {
+    /// MQTT broker host
+    #[arg(long, env, default_value = "localhost")]
+    pub mqtt_host: String,
+
+    /// MQTT broker port
+    #[arg(long, env, default_value_t = 1883)]
+    pub mqtt_port: u16,
+
+    /// MQTT client ID, no value means `simdev-<device address>`
+    #[arg(long, env)]
+    pub mqtt_client_id: Option<String>,
+
+    /// MQTT username
+    #[arg(long, env, requires = "mqtt_password")]
+    pub mqtt_username: Option<String>,
+
+    /// MQTT password
+    #[arg(long, env, requires = "mqtt_username")]
+    pub mqtt_password: Option<String>,
+
+    /// QoS level of published messages: 0, 1 or 2
+    #[arg(long, env, default_value_t = 1, value_parser = clap::value_parser!(u8).range(0..=2))]
+    pub mqtt_qos: u8,
+
+    /// Start with a clean session instead of resuming the previous one
+    #[arg(long, env)]
+    pub mqtt_clean_session: bool,
+
+    /// MQTT keep alive in seconds
+    #[arg(long, env, default_value_t = 30)]
+    pub mqtt_keep_alive: u64,
+}
+
+impl MqttArgs {
+    pub fn qos(&self) -> Result<QoS> {
+        match self.mqtt_qos {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Ok(QoS::ExactlyOnce),
+            q => bail!("Invalid MQTT QoS level: {}", q),
+        }
+    }
+
+    pub fn client_id(&self, addr: &str) -> String {
+        self.mqtt_client_id
+            .clone()
+            .unwrap_or_else(|| format!("simdev-{}", addr))
+    }
+
+    pub fn options(&self, client_id: String) -> MqttOptions {
+        let mut opts = MqttOptions::new(client_id, self.mqtt_host.as_str(), self.mqtt_port);
+        opts.set_keep_alive(Duration::from_secs(self.mqtt_keep_alive));
+        // Keeping the session lets the broker hand over in-flight messages after a reconnect.
+        opts.set_clean_session(self.mqtt_clean_session);
+        if let (Some(username), Some(password)) = (&self.mqtt_username, &self.mqtt_password) {
+            opts.set_credentials(username, password);
+        }
+        opts
+    }
+}
+
+pub struct MqttTransport {
+    client: AsyncClient,
+    qos: QoS,
+}
+
+impl MqttTransport {
+    pub fn connect(args: &MqttArgs, addr: &str) -> Result<Self> {
+        let qos = args.qos()?;
+        let (client, mut eventloop) = AsyncClient::new(args.options(args.client_id(addr)), 64);
+
+        let addr = addr.to_string();
+        tokio::spawn(async move {
+            let mut delay = Duration::from_secs(1);
+            loop {
+                // Polling again after an error makes rumqttc reconnect and resend pending packets.
+                match eventloop.poll().await {
+                    Ok(MqttEvent::Incoming(Packet::ConnAck(ack))) => {
+                        info!(
+                            "[{}] MQTT connected: code={:?} session_present={}",
+                            &addr, ack.code, ack.session_present
+                        );
+                        delay = Duration::from_secs(1);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        error!(
+                            "[{}] MQTT connection: {}, reconnecting in {:?}",
+                            &addr, e, delay
+                        );
+                        sleep(delay).await;
+                        delay = (delay * 2).min(MQTT_RECONNECT_MAX_DELAY);
+                    }
+                }
+            }
+        });
+
+        Ok(Self { client, qos })
+    }
+}
+
+#[async_trait]
+impl Transport for MqttTransport {
+    fn name(&self) -> &'static str {
+        "mqtt"
+    }
+
+    async fn publish(&mut self, msg: &SignedMessage, _raw: &RawMessage) -> Result<u64> {
+        // Don't block the report loop while the broker is away and the request queue is full.
+        self.client
+            .try_publish(DEPHY_TOPIC, self.qos, false, to_vec(msg)?)?;
+        Ok(1)
+    }
+}
@@ -1,4 +1,18 @@
 use crate::preludes::*;
+use crate::transport::{build_transports, Transport, TransportKind};
+use crate::verifier::{MessageVerifier, VerifierArgs};
+use anyhow::ensure;
+use async_trait::async_trait;
+use borsh::{from_slice, to_vec};
+use clap::Args;
+use rumqttc::{AsyncClient, Event as MqttEvent, Packet, QoS};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 
 pub static DEPHY_NOSTR_KIND: Kind = Kind::Regular(1111);
 
@@ -13,149 +27,524 @@ pub fn default_filter(kind: Option<Kind>) -> Filter {
         .custom_tag(Alphabet::C, vec!["dephy"])
 }
 
-// pub async fn start_nostr_context(
-//     ctx: Arc<AppContext>,
-//     cancel_token: CancellationToken,
-// ) -> Result<()> {
-//     let client = ctx.nostr_client.clone();
-//     client.connect().await;
-
-//     // todo: blacklist
-//     let subscription_filter = default_filter(None).since(Timestamp::now());
-//     client.subscribe(vec![subscription_filter]).await;
-//     info!("Subscribing DePHY events from NoStr network...");
-//     client
-//         .handle_notifications(move |n| {
-//             let ctx = ctx.clone();
-//             let cancel_token = cancel_token.clone();
-//             async move {
-//                 if cancel_token.is_cancelled() {
-//                     return Ok(true);
-//                 }
-//                 tokio::spawn(wrap_handle_notification(ctx, n));
-//                 Ok(false)
-//             }
-//         })
-//         .await?;
-
-//     Ok(())
-// }
-
-// async fn handle_notification(ctx: Arc<AppContext>, n: RelayPoolNotification) -> Result<()> {
-//     // todo: blacklist
-//     if let RelayPoolNotification::Event(u, n) = n {
-//         debug!("receiving dephy event from {:?}: {:?}", u, &n);
-
-//         let mut c_dephy = false;
-
-//         let mut edge = None;
-//         let mut from = None;
-//         let mut to = None;
-
-//         for t in n.tags {
-//             if let Tag::Generic(TagKind::Custom(t), m) = t {
-//                 if m.len() == 1 {
-//                     match t.as_str() {
-//                         "c" => c_dephy = m[0].as_str() == "dephy",
-//                         "dephy_edge" => edge = Some(did_str_to_addr_bytes(&m[0])?),
-//                         "dephy_from" => from = Some(did_str_to_addr_bytes(&m[0])?),
-//                         "dephy_to" => to = Some(did_str_to_addr_bytes(&m[0])?),
-//                         _ => {}
-//                     }
-//                 }
-//             }
-//         }
-
-//         if !c_dephy || edge.is_none() || from.is_none() || to.is_none() {
-//             debug!("bad marker");
-//             return Ok(());
-//         }
-
-//         let edge = edge.unwrap();
-//         let from = from.unwrap();
-//         let to = to.unwrap();
-
-//         let curr_addr = &ctx.eth_addr_bytes;
-//         if edge.eq(curr_addr) {
-//             debug!("edge.eq(curr_addr)");
-//             return Ok(());
-//         }
-
-//         let content = bs58::decode(n.content).into_vec()?;
-//         let (mut signed, raw) = check_message(content.as_slice())?;
-
-//         if *&signed.last_edge_addr.is_none() {
-//             debug!("*&signed.last_edge_addr.is_none()");
-//             return Ok(());
-//         }
-
-//         if from.ne(&raw.from_address) || to.ne(&raw.to_address) {
-//             debug!("from==to");
-//             return Ok(());
-//         }
-
-//         signed.last_edge_addr = Some(curr_addr.to_vec());
-//         let content = to_vec(&signed)?;
-
-//         let mqtt_tx = ctx.mqtt_tx.clone();
-//         let mut mqtt_tx = mqtt_tx.lock().await;
-//         mqtt_tx.publish(DEPHY_TOPIC, content)?;
-//         drop(mqtt_tx);
-//     }
-//     Ok(())
-// }
-
-// async fn wrap_handle_notification(ctx: Arc<AppContext>, n: RelayPoolNotification) {
-//     if let Err(e) = handle_notification(ctx, n).await {
-//         debug!("handle_notification: {:?}", e)
-//     }
-// }
-
-// // Forward messages from MQTT/HTTP to NoStr
-// pub async fn send_signed_message_to_network(
-//     ctx: Arc<AppContext>,
-//     client: Arc<Client>,
-//     msg: SignedMessage,
-//     keys: &Keys,
-// ) -> Result<()> {
-//     trace!("send_signed_message_to_network");
-//     let (msg, raw) = check_message(to_vec(&msg)?.as_slice())?;
-
-//     let from_addr = if raw.from_address.len() == 20 {
-//         hex::encode(&raw.from_address)
-//     } else {
-//         bail!("Bad from_addr")
-//     };
-//     let to_addr = if raw.to_address.len() == 20 {
-//         hex::encode(&raw.to_address)
-//     } else {
-//         bail!("Bad to_addr")
-//     };
-
-//     let new_msg = SignedMessage {
-//         raw: msg.raw,
-//         hash: msg.hash,
-//         nonce: msg.nonce,
-//         signature: msg.signature,
-//         last_edge_addr: Some(ctx.eth_addr_bytes.to_vec()),
-//     };
-//     let content = bs58::encode(to_vec(&new_msg)?.as_slice()).into_string();
-//     let tags = vec![
-//         Tag::Generic(TagKind::Custom("c".to_string()), vec!["dephy".to_string()]),
-//         Tag::Generic(
-//             TagKind::Custom("dephy_to".to_string()),
-//             vec![format!("did:dephy:0x{}", to_addr)],
-//         ),
-//         Tag::Generic(
-//             TagKind::Custom("dephy_from".to_string()),
-//             vec![format!("did:dephy:0x{}", from_addr)],
-//         ),
-//         Tag::Generic(
-//             TagKind::Custom("dephy_edge".to_string()),
-//             vec![format!("did:dephy:{}", ctx.eth_addr.as_str())],
-//         ),
-//     ];
-//     let event = EventBuilder::new(default_kind(), content, tags.as_slice()).to_event(keys)?;
-//     client.send_event(event).await?;
-//     Ok(())
-// }
+/// Wrap a signed message into a DePHY event, tagged with its sender, recipient and last edge.
+pub fn dephy_event(msg: &SignedMessage, raw: &RawMessage, keys: &Keys) -> Result<Event> {
+    let edge = msg.last_edge_addr.as_ref().unwrap_or(&raw.from_address);
+    let content = bs58::encode(to_vec(msg)?.as_slice()).into_string();
+    let tags = vec![
+        Tag::Generic(TagKind::Custom("c".to_string()), vec!["dephy".to_string()]),
+        Tag::Generic(
+            TagKind::Custom("dephy_to".to_string()),
+            vec![format!("did:dephy:0x{}", hex::encode(&raw.to_address))],
+        ),
+        Tag::Generic(
+            TagKind::Custom("dephy_from".to_string()),
+            vec![format!("did:dephy:0x{}", hex::encode(&raw.from_address))],
+        ),
+        Tag::Generic(
+            TagKind::Custom("dephy_edge".to_string()),
+            vec![format!("did:dephy:0x{}", hex::encode(edge))],
+        ),
+    ];
+    let ret = EventBuilder::new(default_kind(), content, tags.as_slice()).to_event(keys)?;
+    Ok(ret)
+}
+
+/// Addresses carried in the tags of a DePHY event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DephyTags {
+    pub edge: Vec<u8>,
+    pub from: Vec<u8>,
+    pub to: Vec<u8>,
+}
+
+pub fn parse_dephy_tags(tags: &[Tag]) -> Result<DephyTags> {
+    let mut c_dephy = false;
+
+    let mut edge = None;
+    let mut from = None;
+    let mut to = None;
+
+    for t in tags.iter() {
+        if let Tag::Generic(TagKind::Custom(t), m) = t {
+            if m.len() == 1 {
+                match t.as_str() {
+                    "c" => c_dephy = m[0].as_str() == "dephy",
+                    "dephy_edge" => edge = Some(did_str_to_addr_bytes(&m[0])?),
+                    "dephy_from" => from = Some(did_str_to_addr_bytes(&m[0])?),
+                    "dephy_to" => to = Some(did_str_to_addr_bytes(&m[0])?),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    ensure!(c_dephy, "Event is not tagged as a DePHY event.");
+    Ok(DephyTags {
+        edge: edge.ok_or(anyhow!("Missing dephy_edge tag."))?,
+        from: from.ok_or(anyhow!("Missing dephy_from tag."))?,
+        to: to.ok_or(anyhow!("Missing dephy_to tag."))?,
+    })
+}
+
+/// Inverse of `dephy_event`, checks the event and message signatures and that the
+/// tags agree with the signed message.
+pub fn parse_nostr_event(event: &Event) -> Result<(SignedMessage, RawMessage, DephyTags)> {
+    ensure!(
+        event.kind == default_kind(),
+        "Unexpected event kind: {:?}",
+        event.kind
+    );
+    event.verify()?;
+    let tags = parse_dephy_tags(&event.tags)?;
+
+    let content = bs58::decode(event.content.as_str()).into_vec()?;
+    let (msg, raw) = check_message(content.as_slice())?;
+    check_dephy_tags(&tags, &msg, &raw)?;
+    Ok((msg, raw, tags))
+}
+
+/// Check that the tags of a DePHY event agree with the message it carries.
+pub fn check_dephy_tags(tags: &DephyTags, msg: &SignedMessage, raw: &RawMessage) -> Result<()> {
+    ensure!(
+        tags.from == raw.from_address,
+        "dephy_from tag 0x{} does not match sender 0x{}",
+        hex::encode(&tags.from),
+        hex::encode(&raw.from_address)
+    );
+    ensure!(
+        tags.to == raw.to_address,
+        "dephy_to tag 0x{} does not match recipient 0x{}",
+        hex::encode(&tags.to),
+        hex::encode(&raw.to_address)
+    );
+    let edge = msg.last_edge_addr.as_ref().unwrap_or(&raw.from_address);
+    ensure!(
+        &tags.edge == edge,
+        "dephy_edge tag 0x{} does not match last edge 0x{}",
+        hex::encode(&tags.edge),
+        hex::encode(edge)
+    );
+    Ok(())
+}
+
+/// Nostr keys sharing the secret of a DePHY signer.
+pub fn signer_nostr_keys(signer: &SigningKey) -> Result<Keys> {
+    let sk = SecretKey::from_slice(signer.to_bytes().as_slice())?;
+    Ok(Keys::new(sk))
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct NostrArgs {
+    /// Nostr relays to publish DePHY events to
+    #[arg(
+        long,
+        env,
+        value_delimiter = ',',
+        default_value = "wss://poc-relay.dephy.cloud"
+    )]
+    pub nostr_relay: Vec<String>,
+
+    /// Nostr secret key in hex or bech32, no value means the key of the device signer
+    #[arg(long, env)]
+    pub nostr_key: Option<String>,
+}
+
+impl NostrArgs {
+    pub fn keys(&self, signer: &SigningKey) -> Result<Keys> {
+        match &self.nostr_key {
+            Some(key) => Ok(Keys::from_sk_str(key.as_str())?),
+            None => signer_nostr_keys(signer),
+        }
+    }
+}
+
+pub async fn connect_client(relays: &[String], keys: &Keys) -> Result<Client> {
+    let client = Client::new(keys);
+    for relay in relays.iter() {
+        client.add_relay(relay.as_str(), None).await?;
+        debug!("Added Nostr relay {}", relay);
+    }
+    client.connect().await;
+    Ok(client)
+}
+
+pub struct NostrTransport {
+    client: Client,
+    keys: Keys,
+}
+
+impl NostrTransport {
+    pub async fn connect(args: &NostrArgs, signer: &SigningKey) -> Result<Self> {
+        if args.nostr_relay.is_empty() {
+            bail!("Nostr transport requires at least one --nostr-relay.");
+        }
+        let keys = args.keys(signer)?;
+        info!(
+            "[{}] Nostr public key: {}",
+            get_eth_address(signer.verifying_key()),
+            keys.public_key()
+        );
+        let client = connect_client(&args.nostr_relay, &keys).await?;
+        Ok(Self { client, keys })
+    }
+}
+
+#[async_trait]
+impl Transport for NostrTransport {
+    fn name(&self) -> &'static str {
+        "nostr"
+    }
+
+    async fn publish(&mut self, msg: &SignedMessage, raw: &RawMessage) -> Result<u64> {
+        let event = dephy_event(msg, raw, &self.keys)?;
+        self.client.send_event(event).await?;
+        Ok(1)
+    }
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct EdgeArgs {
+    /// Edge signer, no value means random signer
+    #[arg(long, env)]
+    pub edge_key: Option<String>,
+
+    /// Channels to forward DePHY events received from Nostr to
+    #[arg(long, env, value_enum, value_delimiter = ',', default_value = "mqtt")]
+    pub forward: Vec<TransportKind>,
+
+    /// Also publish device messages received on DEPHY_TOPIC over MQTT to Nostr
+    #[arg(long, env)]
+    pub mqtt_ingress: bool,
+
+    /// DIDs whose messages are ignored, as sender or as last edge
+    #[arg(long, env, value_delimiter = ',')]
+    pub blacklist: Vec<String>,
+
+    /// File with one blacklisted DID per line
+    #[arg(long, env)]
+    pub blacklist_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub verifier: VerifierArgs,
+}
+
+impl EdgeArgs {
+    pub fn load_blacklist(&self) -> Result<HashSet<Vec<u8>>> {
+        let mut ret = HashSet::new();
+        for did in self.blacklist.iter() {
+            ret.insert(did_str_to_addr_bytes(did.trim())?);
+        }
+        if let Some(path) = &self.blacklist_file {
+            let data = std::fs::read_to_string(path)?;
+            for line in data.lines().map(|l| l.trim()) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                ret.insert(did_str_to_addr_bytes(line)?);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+pub struct NostrEdgeContext {
+    pub nostr_client: Client,
+    pub keys: Keys,
+    pub eth_addr: String,
+    pub eth_addr_bytes: Bytes,
+    pub blacklist: HashSet<Vec<u8>>,
+    pub forward: Mutex<Vec<Box<dyn Transport>>>,
+    pub verifier: Mutex<MessageVerifier>,
+}
+
+impl NostrEdgeContext {
+    pub fn is_blacklisted(&self, addr: &[u8]) -> bool {
+        self.blacklist.contains(addr)
+    }
+}
+
+pub async fn run_edge_main(cmd: Cmd, args: EdgeArgs) -> Result<()> {
+    let signer = match &args.edge_key {
+        None => random_signing_key(),
+        Some(key) => parse_signing_key(key.replace("0x", ""))?,
+    };
+    let eth_addr = get_eth_address(signer.verifying_key());
+    info!("Edge signer: {}", &eth_addr);
+
+    ensure!(
+        !args.forward.contains(&TransportKind::Nostr),
+        "Forwarding Nostr events back to Nostr would loop."
+    );
+    let forward = build_transports(&args.forward, &cmd, &signer, &eth_addr, None).await?;
+
+    let keys = cmd.nostr.keys(&signer)?;
+    let nostr_client = connect_client(&cmd.nostr.nostr_relay, &keys).await?;
+    let blacklist = args.load_blacklist()?;
+    if !blacklist.is_empty() {
+        info!("Ignoring {} blacklisted DIDs", blacklist.len());
+    }
+
+    let ctx = Arc::new(NostrEdgeContext {
+        nostr_client,
+        keys,
+        eth_addr,
+        eth_addr_bytes: signer.eth_addr(),
+        blacklist,
+        forward: Mutex::new(forward),
+        verifier: Mutex::new(MessageVerifier::new(
+            &args.verifier,
+            vec![CURRENT_HASH_SCHEME],
+        )),
+    });
+
+    let cancel_token = CancellationToken::new();
+    let c = cancel_token.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("Shutting down edge...");
+        }
+        c.cancel();
+    });
+
+    if args.mqtt_ingress {
+        let ctx = ctx.clone();
+        let cmd = cmd.clone();
+        let cancel_token = cancel_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = start_mqtt_ingress(ctx, &cmd, cancel_token).await {
+                error!("MQTT ingress: {}", e);
+            }
+        });
+    }
+
+    start_nostr_context(ctx, cancel_token).await
+}
+
+pub async fn start_nostr_context(
+    ctx: Arc<NostrEdgeContext>,
+    cancel_token: CancellationToken,
+) -> Result<()> {
+    let client = ctx.nostr_client.clone();
+    client.connect().await;
+
+    let subscription_filter = default_filter(None).since(Timestamp::now());
+    client.subscribe(vec![subscription_filter]).await;
+    info!("Subscribing DePHY events from NoStr network...");
+
+    let t = cancel_token.clone();
+    let handle = client.handle_notifications(move |n| {
+        let ctx = ctx.clone();
+        let cancel_token = t.clone();
+        async move {
+            if cancel_token.is_cancelled() {
+                return Ok(true);
+            }
+            tokio::spawn(wrap_handle_notification(ctx, n));
+            Ok(false)
+        }
+    });
+    tokio::select! {
+        _ = cancel_token.cancelled() => {}
+        res = handle => res?,
+    }
+
+    client.disconnect().await?;
+    Ok(())
+}
+
+async fn handle_notification(ctx: Arc<NostrEdgeContext>, n: RelayPoolNotification) -> Result<()> {
+    if let RelayPoolNotification::Event(u, n) = n {
+        debug!("receiving dephy event from {:?}: {:?}", u, &n);
+
+        let tags = match parse_dephy_tags(&n.tags) {
+            Ok(tags) => tags,
+            Err(e) => {
+                debug!("bad marker: {}", e);
+                return Ok(());
+            }
+        };
+
+        let curr_addr = &ctx.eth_addr_bytes;
+        if tags.edge.eq(curr_addr) {
+            debug!("edge.eq(curr_addr)");
+            return Ok(());
+        }
+
+        if ctx.is_blacklisted(&tags.from) || ctx.is_blacklisted(&tags.edge) {
+            debug!(
+                "blacklisted: from=0x{} edge=0x{}",
+                hex::encode(&tags.from),
+                hex::encode(&tags.edge)
+            );
+            return Ok(());
+        }
+
+        let content = bs58::decode(n.content).into_vec()?;
+        // Cheap checks go first so that a mismatching copy is not remembered by the verifier.
+        let unchecked = from_slice::<SignedMessage>(content.as_slice())?;
+        if unchecked.last_edge_addr.is_none() {
+            debug!("signed.last_edge_addr.is_none()");
+            return Ok(());
+        }
+        let unchecked_raw = from_slice::<RawMessage>(unchecked.raw.as_slice())?;
+        if let Err(e) = check_dephy_tags(&tags, &unchecked, &unchecked_raw) {
+            debug!("{}", e);
+            return Ok(());
+        }
+
+        // The same event usually arrives from several relays or edges, only forward it once.
+        let (mut signed, raw, _) = ctx.verifier.lock().await.verify(content.as_slice())?;
+
+        signed.last_edge_addr = Some(curr_addr.to_vec());
+
+        let mut forward = ctx.forward.lock().await;
+        for t in forward.iter_mut() {
+            if let Err(e) = t.publish(&signed, &raw).await {
+                error!("Forwarding over {}: {}", t.name(), e);
+            }
+        }
+        drop(forward);
+    }
+    Ok(())
+}
+
+async fn wrap_handle_notification(ctx: Arc<NostrEdgeContext>, n: RelayPoolNotification) {
+    if let Err(e) = handle_notification(ctx, n).await {
+        debug!("handle_notification: {:?}", e)
+    }
+}
+
+// Forward messages from MQTT/HTTP to NoStr
+pub async fn send_signed_message_to_network(
+    ctx: Arc<NostrEdgeContext>,
+    msg: SignedMessage,
+) -> Result<()> {
+    let (msg, raw, _) = ctx.verifier.lock().await.verify(to_vec(&msg)?.as_slice())?;
+
+    ensure!(raw.from_address.len() == 20, "Bad from_addr");
+    ensure!(raw.to_address.len() == 20, "Bad to_addr");
+    ensure!(
+        !ctx.is_blacklisted(&raw.from_address),
+        "Sender 0x{} is blacklisted",
+        hex::encode(&raw.from_address)
+    );
+
+    let new_msg = SignedMessage {
+        last_edge_addr: Some(ctx.eth_addr_bytes.to_vec()),
+        ..msg
+    };
+    let event = dephy_event(&new_msg, &raw, &ctx.keys)?;
+    ctx.nostr_client.send_event(event).await?;
+    Ok(())
+}
+
+async fn start_mqtt_ingress(
+    ctx: Arc<NostrEdgeContext>,
+    cmd: &Cmd,
+    cancel_token: CancellationToken,
+) -> Result<()> {
+    let client_id = format!("{}-ingress", cmd.mqtt.client_id(&ctx.eth_addr));
+    let (client, mut eventloop) = AsyncClient::new(cmd.mqtt.options(client_id), 64);
+    info!("Receiving device messages from MQTT topic {}", DEPHY_TOPIC);
+
+    loop {
+        let event = tokio::select! {
+            _ = cancel_token.cancelled() => break,
+            event = eventloop.poll() => event,
+        };
+        match event {
+            Ok(MqttEvent::Incoming(Packet::ConnAck(_))) => {
+                client.subscribe(DEPHY_TOPIC, QoS::AtLeastOnce).await?;
+            }
+            Ok(MqttEvent::Incoming(Packet::Publish(p))) => {
+                let msg = match from_slice::<SignedMessage>(&p.payload) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        debug!("Bad MQTT payload: {}", e);
+                        continue;
+                    }
+                };
+                // Messages forwarded by an edge carry the edge address, only fresh device
+                // messages are sent on so that edges do not bounce events between each other.
+                let raw_from = from_slice::<RawMessage>(&msg.raw).map(|r| r.from_address);
+                if raw_from.ok() != msg.last_edge_addr {
+                    continue;
+                }
+                if let Err(e) = send_signed_message_to_network(ctx.clone(), msg).await {
+                    debug!("send_signed_message_to_network: {:?}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                error!("MQTT ingress connection: {}", e);
+                sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static OTHER: &str = "did:dephy:0x00000000000000000000000000000000000000aa";
+
+    async fn event(key: &SigningKey) -> (SignedMessage, RawMessage, Event) {
+        let (msg, raw) = key
+            .create_message(
+                vec![0u8; 16],
+                None,
+                MessageChannel::Normal(0),
+                b"test".to_vec(),
+                Some(vec![0xbb; 20]),
+                None,
+            )
+            .await
+            .unwrap();
+        let event = dephy_event(&msg, &raw, &signer_nostr_keys(key).unwrap()).unwrap();
+        (msg, raw, event)
+    }
+
+    /// `event` signed again with the value of tag `name` replaced by `value`.
+    fn retag(event: &Event, key: &SigningKey, name: &str, value: &str) -> Event {
+        let tags: Vec<Tag> = event
+            .tags
+            .iter()
+            .map(|t| match t {
+                Tag::Generic(TagKind::Custom(n), _) if n == name => {
+                    Tag::Generic(TagKind::Custom(n.clone()), vec![value.to_string()])
+                }
+                t => t.clone(),
+            })
+            .collect();
+        EventBuilder::new(default_kind(), event.content.clone(), tags.as_slice())
+            .to_event(&signer_nostr_keys(key).unwrap())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn parses_dephy_events() {
+        let key = random_signing_key();
+        let (msg, raw, event) = event(&key).await;
+        let (parsed, parsed_raw, tags) = parse_nostr_event(&event).unwrap();
+        assert_eq!(parsed.hash, msg.hash);
+        assert_eq!(parsed.signature, msg.signature);
+        assert_eq!(parsed_raw.payload, raw.payload);
+        assert_eq!(tags.from, raw.from_address);
+        assert_eq!(tags.to, vec![0xbb; 20]);
+        assert_eq!(tags.edge, raw.from_address);
+    }
+
+    #[tokio::test]
+    async fn rejects_mismatching_tags() {
+        let key = random_signing_key();
+        let (_, _, event) = event(&key).await;
+        for name in ["dephy_from", "dephy_to", "dephy_edge"] {
+            let event = retag(&event, &key, name, OTHER);
+            assert!(parse_nostr_event(&event).is_err(), "{} not checked", name);
+        }
+        assert!(parse_nostr_event(&retag(&event, &key, "c", "other")).is_err());
+    }
+}
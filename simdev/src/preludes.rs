@@ -1,4 +1,16 @@
+use crate::control::ControlArgs;
 pub use crate::crypto::*;
+use crate::discovery::DiscoverArgs;
+use crate::health::NodeHealthStatus;
+use crate::mock_edge::MockEdgeArgs;
+use crate::mqtt::MqttArgs;
+use crate::nostr::{EdgeArgs, NostrArgs};
+use crate::queue::QueueArgs;
+use crate::rings::{DeviceEvent, RingsArgs};
+use crate::sensor::SensorArgs;
+use crate::sim::DEFAULT_SIM_EPOCH;
+use crate::transport::TransportKind;
+use crate::vectors::VectorsArgs;
 pub use anyhow::{anyhow, bail, Result};
 pub use bytes::Bytes;
 use clap::{Args, Parser, Subcommand};
@@ -17,8 +29,27 @@ pub static DEPHY_P2P_TOPIC_PREFIX: &'static str = "/dephy/p2p/";
 
 pub static ETH_ADDRESS_PREFIX: &'static str = "0x";
 
+pub static DEFAULT_RINGS_RELAY_ENDPOINT: &str = "https://poc-rings.dephy.cloud";
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum SimdevCommand {
+    /// Run a DePHY edge forwarding events between Nostr and other transports
+    Edge(EdgeArgs),
+    /// Run a local mock of the DePHY edge HTTP endpoint
+    MockEdge(MockEdgeArgs),
+    /// Decrypt the payload of an encrypted signed message
+    Decrypt(DecryptArgs),
+    /// Verify the signed message test vectors, or regenerate them with `--generate`
+    Vectors(VectorsArgs),
+    /// List the DIDs of devices registered under a Rings service
+    Discover(DiscoverArgs),
+}
+
 #[derive(Parser, Clone, Debug)]
 pub struct Cmd {
+    #[command(subcommand)]
+    pub command: Option<SimdevCommand>,
+
     #[arg(
         short = 'd',
         long,
@@ -30,10 +61,25 @@ pub struct Cmd {
         short = 'r',
         long,
         env,
-        default_value = "https://poc-rings.dephy.cloud"
+        default_value = DEFAULT_RINGS_RELAY_ENDPOINT
     )]
     pub rings_relay_endpoint: String,
 
+    /// Rings DID receiving reports when the rings transport is selected
+    #[arg(long, env)]
+    pub rings_destination: Option<String>,
+
+    /// Channels to publish reports over
+    #[arg(
+        short,
+        long,
+        env,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "http"
+    )]
+    pub transport: Vec<TransportKind>,
+
     /// Report signer, no value means random signer
     #[arg(short, long, env)]
     pub from: Option<String>,
@@ -41,6 +87,111 @@ pub struct Cmd {
     /// Send interval in seconds
     #[arg(short, long, env, default_value_t = 10)]
     pub interval: u64,
+
+    /// Recipient address of reports, as 0x hex or did:dephy DID
+    #[arg(
+        long,
+        env,
+        default_value = "0x0000000000000000000000000000000000000000"
+    )]
+    pub report_to: String,
+
+    /// Message channels of reports, several channels are used in turn
+    #[arg(long, env, value_delimiter = ',', default_value = "233")]
+    pub channel: Vec<u8>,
+
+    /// Hashing scheme of signed reports
+    #[arg(long, env, value_enum, default_value_t = CURRENT_HASH_SCHEME)]
+    pub hash_scheme: HashScheme,
+
+    /// Encrypt report payloads to this SEC1 public key in hex, reports are then addressed to it
+    #[arg(long, env, conflicts_with = "encrypt_to_file")]
+    pub encrypt_to: Option<String>,
+
+    /// File containing the SEC1 public key in hex to encrypt report payloads to
+    #[arg(long, env)]
+    pub encrypt_to_file: Option<PathBuf>,
+
+    /// Stop after sending this many reports, no value means run forever
+    #[arg(long, env)]
+    pub count: Option<u64>,
+
+    /// Seed for keys, readings and IVs, also switches to a simulated clock
+    #[arg(long, env)]
+    pub seed: Option<u64>,
+
+    /// Start time in unix seconds of the simulated clock used with --seed
+    #[arg(long, env, default_value_t = DEFAULT_SIM_EPOCH)]
+    pub sim_epoch: u64,
+
+    #[command(flatten)]
+    pub sensor: SensorArgs,
+
+    #[command(flatten)]
+    pub queue: QueueArgs,
+
+    #[command(flatten)]
+    pub mqtt: MqttArgs,
+
+    #[command(flatten)]
+    pub nostr: NostrArgs,
+
+    #[command(flatten)]
+    pub control: ControlArgs,
+
+    #[command(flatten)]
+    pub rings: RingsArgs,
+
+    /// Run N independent simulated devices in this process
+    #[arg(long, env, conflicts_with = "fleet_manifest")]
+    pub fleet: Option<usize>,
+
+    /// JSON file listing fleet devices, see `FleetDevice` for the fields
+    #[arg(long, env)]
+    pub fleet_manifest: Option<PathBuf>,
+
+    /// Interval in seconds between per-device metrics logs in fleet mode, 0 disables them
+    #[arg(long, env, default_value_t = 60)]
+    pub fleet_metrics_interval: u64,
+}
+
+impl Cmd {
+    /// Bootstrap nodes of the Rings node. An empty relay endpoint leaves the node waiting
+    /// for peers, the default one is dropped in LAN mode.
+    pub fn rings_bootstrap_urls(&self) -> Vec<String> {
+        let url = self.rings_relay_endpoint.trim();
+        if url.is_empty() || (self.rings.rings_lan && url == DEFAULT_RINGS_RELAY_ENDPOINT) {
+            return vec![];
+        }
+        vec![url.to_string()]
+    }
+
+    pub fn report_to_bytes(&self) -> Result<Vec<u8>> {
+        parse_addr_bytes(self.report_to.as_str())
+    }
+
+    pub fn channels(&self) -> Result<Vec<MessageChannel>> {
+        if self.channel.is_empty() {
+            bail!("At least one channel should be set.");
+        }
+        Ok(self
+            .channel
+            .iter()
+            .map(|c| MessageChannel::Normal(*c))
+            .collect())
+    }
+
+    /// Recipient of encrypted reports, if any.
+    pub fn encr_target(&self) -> Result<Option<k256::PublicKey>> {
+        if let Some(key) = &self.encrypt_to {
+            return Ok(Some(parse_public_key(key.as_str())?));
+        }
+        if let Some(path) = &self.encrypt_to_file {
+            let key = std::fs::read_to_string(path)?;
+            return Ok(Some(parse_public_key(key)?));
+        }
+        Ok(None)
+    }
 }
 
 fn get_relative_path(p: &str) -> PathBuf {
@@ -58,4 +209,6 @@ pub enum GuiAppMessage {
     Message(String),
     CopyToClipboard(String),
     UpdateWeight(f64),
+    DeviceEvent(DeviceEvent),
+    NodeHealth(NodeHealthStatus),
 }
@@ -0,0 +1,339 @@
+use crate::preludes::*;
+use crate::sim::{Clock, SystemClock};
+use clap::Args;
+use rand::{Rng, RngCore};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::Instant;
+
+static QUEUE_FILE_EXT: &str = "msg";
+
+#[derive(Args, Clone, Debug)]
+pub struct QueueArgs {
+    /// Directory keeping reports that failed to publish, no value disables the queue
+    #[arg(long, env)]
+    pub queue_dir: Option<PathBuf>,
+
+    /// Maximum number of queued reports per device, the oldest are dropped first
+    #[arg(long, env, default_value_t = 10000)]
+    pub queue_max_items: usize,
+
+    /// Maximum age in seconds of a queued report before it is dropped
+    #[arg(long, env, default_value_t = 86400)]
+    pub queue_max_age: u64,
+
+    /// First retry delay in milliseconds, doubled after each failure
+    #[arg(long, env, default_value_t = 1000)]
+    pub retry_base_ms: u64,
+
+    /// Upper bound of the retry delay in milliseconds
+    #[arg(long, env, default_value_t = 60000)]
+    pub retry_max_ms: u64,
+}
+
+impl QueueArgs {
+    /// Open the queue of one device, stored under `<queue_dir>/<addr>`.
+    pub fn open(&self, addr: &str) -> Result<Option<OfflineQueue>> {
+        match &self.queue_dir {
+            None => Ok(None),
+            Some(dir) => Ok(Some(OfflineQueue::open(
+                dir.join(addr),
+                self.queue_max_items,
+                Duration::from_secs(self.queue_max_age),
+            )?)),
+        }
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        Backoff::new(
+            Duration::from_millis(self.retry_base_ms),
+            Duration::from_millis(self.retry_max_ms),
+        )
+    }
+}
+
+struct QueueEntry {
+    seq: u64,
+    /// Unix time in seconds
+    enqueued_at: u64,
+    path: PathBuf,
+}
+
+fn parse_entry(path: &Path) -> Option<QueueEntry> {
+    if path.extension()?.to_str()? != QUEUE_FILE_EXT {
+        return None;
+    }
+    let (seq, enqueued_at) = path.file_stem()?.to_str()?.split_once('-')?;
+    Some(QueueEntry {
+        seq: seq.parse().ok()?,
+        enqueued_at: enqueued_at.parse().ok()?,
+        path: path.to_path_buf(),
+    })
+}
+
+/// On-disk FIFO of encoded `SignedMessage`s, one file per message.
+///
+/// The directory is only listed in `open`, later changes go through `entries`.
+pub struct OfflineQueue {
+    dir: PathBuf,
+    max_items: usize,
+    max_age: Duration,
+    next_seq: u64,
+    entries: VecDeque<QueueEntry>,
+}
+
+impl OfflineQueue {
+    pub fn open<P: Into<PathBuf>>(dir: P, max_items: usize, max_age: Duration) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut entries = vec![];
+        for e in fs::read_dir(&dir)? {
+            if let Some(entry) = parse_entry(&e?.path()) {
+                entries.push(entry);
+            }
+        }
+        entries.sort_by_key(|e| e.seq);
+        let next_seq = entries.last().map(|e| e.seq + 1).unwrap_or(0);
+        Ok(Self {
+            dir,
+            max_items: max_items.max(1),
+            max_age,
+            next_seq,
+            entries: entries.into(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, message: &[u8]) -> Result<()> {
+        let name = format!("{:020}-{}", self.next_seq, SystemClock.now());
+        let tmp = self.dir.join(format!("{}.tmp", &name));
+        let path = self.dir.join(format!("{}.{}", &name, QUEUE_FILE_EXT));
+        fs::write(&tmp, message)?;
+        fs::rename(&tmp, &path)?;
+        if let Some(entry) = parse_entry(&path) {
+            self.entries.push_back(entry);
+        }
+        self.next_seq += 1;
+
+        if self.entries.len() > self.max_items {
+            let excess = self.entries.len() - self.max_items;
+            warn!(
+                "Offline queue {} is full, dropping {} oldest messages",
+                self.dir.display(),
+                excess
+            );
+            for _ in 0..excess {
+                self.pop()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Oldest message still within the age limit, expired and unreadable ones are dropped
+    /// on the way.
+    pub fn front(&mut self) -> Result<Option<Vec<u8>>> {
+        let now = SystemClock.now();
+        while let Some(e) = self.entries.front() {
+            if now.saturating_sub(e.enqueued_at) > self.max_age.as_secs() {
+                warn!("Dropping expired queued message {}", e.path.display());
+            } else {
+                match fs::read(&e.path) {
+                    Ok(message) => return Ok(Some(message)),
+                    // Reading it again would fail the same way and hold back the others.
+                    Err(err) => warn!(
+                        "Dropping unreadable queued message {}: {}",
+                        e.path.display(),
+                        err
+                    ),
+                }
+            }
+            self.pop()?;
+        }
+        Ok(None)
+    }
+
+    /// Drop the oldest message.
+    pub fn pop(&mut self) -> Result<()> {
+        if let Some(e) = self.entries.pop_front() {
+            if let Err(err) = fs::remove_file(&e.path) {
+                // Already gone, e.g. removed by hand
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Exponential backoff with jitter between retries.
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    failures: u32,
+    next_try: Instant,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max: max.max(base),
+            failures: 0,
+            next_try: Instant::now(),
+        }
+    }
+
+    pub fn next_try(&self) -> Instant {
+        self.next_try
+    }
+
+    pub fn is_ready(&self) -> bool {
+        Instant::now() >= self.next_try
+    }
+
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.next_try = Instant::now();
+    }
+
+    /// Record a failure and schedule the next try, returns the delay chosen.
+    pub fn fail(&mut self, rng: &mut dyn RngCore) -> Duration {
+        let exp = self.base.saturating_mul(1 << self.failures.min(16));
+        let delay = exp.min(self.max);
+        // Jitter between half and the full delay so that devices do not retry in lockstep.
+        let delay = Duration::from_millis(
+            rng.gen_range(delay.as_millis() as u64 / 2..=delay.as_millis() as u64),
+        );
+        self.failures = self.failures.saturating_add(1);
+        self.next_try = Instant::now() + delay;
+        delay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    /// Directory under the system temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("simdev-queue-{}", OsRng.next_u64()));
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    static DAY: Duration = Duration::from_secs(86400);
+
+    fn drain(queue: &mut OfflineQueue) -> Vec<Vec<u8>> {
+        let mut ret = vec![];
+        while let Some(message) = queue.front().unwrap() {
+            ret.push(message);
+            queue.pop().unwrap();
+        }
+        ret
+    }
+
+    fn files(dir: &Path) -> usize {
+        fs::read_dir(dir).unwrap().count()
+    }
+
+    #[test]
+    fn keeps_order_across_reopen() {
+        let dir = TempDir::new();
+        let mut queue = OfflineQueue::open(&dir.0, 10, DAY).unwrap();
+        for message in [b"a", b"b", b"c"] {
+            queue.push(message).unwrap();
+        }
+        drop(queue);
+
+        let mut queue = OfflineQueue::open(&dir.0, 10, DAY).unwrap();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.front().unwrap(), Some(b"a".to_vec()));
+        queue.pop().unwrap();
+        queue.push(b"d").unwrap();
+        drop(queue);
+
+        let mut queue = OfflineQueue::open(&dir.0, 10, DAY).unwrap();
+        assert_eq!(drain(&mut queue), vec![b"b", b"c", b"d"]);
+        assert!(queue.is_empty());
+        assert_eq!(files(&dir.0), 0);
+    }
+
+    #[test]
+    fn drops_oldest_beyond_max_items() {
+        let dir = TempDir::new();
+        let mut queue = OfflineQueue::open(&dir.0, 2, DAY).unwrap();
+        for message in [b"a", b"b", b"c"] {
+            queue.push(message).unwrap();
+        }
+        assert_eq!(queue.len(), 2);
+        assert_eq!(files(&dir.0), 2);
+        assert_eq!(drain(&mut queue), vec![b"b", b"c"]);
+    }
+
+    #[test]
+    fn drops_expired_messages() {
+        let dir = TempDir::new();
+        fs::create_dir_all(&dir.0).unwrap();
+        let old = SystemClock.now() - DAY.as_secs() - 1;
+        fs::write(dir.0.join(format!("{:020}-{}.msg", 0, old)), b"old").unwrap();
+
+        let mut queue = OfflineQueue::open(&dir.0, 10, DAY).unwrap();
+        queue.push(b"new").unwrap();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(drain(&mut queue), vec![b"new"]);
+        assert_eq!(files(&dir.0), 0);
+    }
+
+    #[test]
+    fn skips_unreadable_messages() {
+        let dir = TempDir::new();
+        let mut queue = OfflineQueue::open(&dir.0, 10, DAY).unwrap();
+        queue.push(b"a").unwrap();
+        queue.push(b"b").unwrap();
+        for e in fs::read_dir(&dir.0).unwrap() {
+            let path = e.unwrap().path();
+            if fs::read(&path).unwrap() == b"a" {
+                fs::remove_file(&path).unwrap();
+            }
+        }
+        assert_eq!(drain(&mut queue), vec![b"b"]);
+    }
+
+    #[test]
+    fn backoff_grows_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(1000));
+        for expected in [100, 200, 400, 800, 1000, 1000, 1000] {
+            let delay = backoff.fail(&mut OsRng).as_millis() as u64;
+            assert!(
+                (expected / 2..=expected).contains(&delay),
+                "delay {}ms, expected {}ms",
+                delay,
+                expected
+            );
+            assert!(!backoff.is_ready());
+        }
+        backoff.reset();
+        assert!(backoff.is_ready());
+        assert!(backoff.fail(&mut OsRng) <= Duration::from_millis(100));
+    }
+}
@@ -1,22 +1,40 @@
+use crate::bootstrap::BootstrapSupervisor;
+use crate::control::DeviceControl;
+use crate::discovery::{device_service, spawn_service_registration};
+use crate::health::NodeHealth;
+use crate::lan::serve_rings_endpoint;
 use crate::preludes::*;
 use crate::rings::AppRingsProvider;
 use crate::rings::BackendBehaviour;
+use crate::rings::DeviceEvent;
+use crate::rings::RingsStorage;
+use crate::sensor::SensorModel;
+use crate::sim::{Clock, SimRng, SteppedClock, SystemClock};
+use crate::status::spawn_status_publisher;
+use crate::transport::build_transports;
 use borsh::{to_vec, BorshDeserialize, BorshSerialize};
 use dephy_edge::preludes::DephySessionStore;
 use futures::SinkExt;
 use iced::futures::channel::mpsc::Sender;
-use rand::rngs::OsRng;
-use rand::Rng;
+use rand::RngCore;
 use rings_node::provider::Provider;
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
-use tokio::time::sleep;
+use tokio::sync::{Mutex, Notify};
+use tokio::time::{sleep_until, Instant};
 
 #[derive(Clone)]
 pub struct DeviceContext {
     pub weight: f64,
     pub session: DephySessionStore,
+    pub addr: Option<String>,
+    pub metrics: DeviceMetrics,
+    pub control: DeviceControl,
+    /// Signalled by `apply_control_command`, wakes the report loop
+    pub control_changed: Arc<Notify>,
+    pub bootstrap: Option<Arc<BootstrapSupervisor>>,
+    pub health: Option<Arc<NodeHealth>>,
 }
 
 impl Default for DeviceContext {
@@ -24,6 +42,67 @@ impl Default for DeviceContext {
         Self {
             weight: 1.0,
             session: DephySessionStore::new(),
+            addr: None,
+            metrics: DeviceMetrics::default(),
+            control: DeviceControl::default(),
+            control_changed: Arc::new(Notify::new()),
+            bootstrap: None,
+            health: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DeviceMetrics {
+    pub reports: u64,
+    pub published: u64,
+    pub publish_errors: u64,
+    pub last_report: Option<f64>,
+    /// DIDs of the P2P peers connected right now
+    pub peers: HashSet<String>,
+    pub peer_connects: u64,
+    pub peer_disconnects: u64,
+}
+
+impl DeviceMetrics {
+    pub fn record_event(&mut self, event: &DeviceEvent) {
+        match event {
+            DeviceEvent::PeerConnected { did } => {
+                if self.peers.insert(did.clone()) {
+                    self.peer_connects += 1;
+                }
+            }
+            DeviceEvent::PeerDisconnected { did, .. } => {
+                if self.peers.remove(did) {
+                    self.peer_disconnects += 1;
+                }
+            }
+            DeviceEvent::PeerStateChanged { .. } => {}
+        }
+    }
+}
+
+/// Sleep until the report interval has passed since `started`, when the device had
+/// `before` as control state. Control commands wake the device, a new interval applies
+/// to the current wait and a pause, resume or session rotation ends it.
+async fn wait_for_next_report(
+    ctx: &Mutex<DeviceContext>,
+    before: &DeviceControl,
+    interval: u64,
+    started: Instant,
+) {
+    let changed = ctx.lock().await.control_changed.clone();
+    loop {
+        let control = ctx.lock().await.control.clone();
+        if control.paused != before.paused
+            || control.session_generation != before.session_generation
+        {
+            return;
+        }
+        let d = Duration::from_secs(control.interval.unwrap_or(interval));
+        tokio::select! {
+            _ = sleep_until(started + d) => return,
+            _ = changed.notified() => {}
         }
     }
 }
@@ -40,16 +119,30 @@ pub async fn run_device_main(
     ctx: Arc<Mutex<DeviceContext>>,
     tx: Option<Sender<GuiAppMessage>>,
 ) -> Result<()> {
-    let report_to: Vec<_> = [0u8; 20].into();
+    let report_to = cmd.report_to_bytes()?;
+    let channels = cmd.channels()?;
+    let mut rng = SimRng::new(cmd.seed);
+    let clock: Arc<dyn Clock> = match cmd.seed {
+        Some(_) => Arc::new(SteppedClock::new(cmd.sim_epoch)),
+        None => Arc::new(SystemClock),
+    };
     let signer = match &cmd.from {
-        None => random_signing_key(),
+        None => random_signing_key_with(&mut rng),
         Some(key) => parse_signing_key(key.replace("0x", ""))?,
     };
-    let d = Duration::from_secs(cmd.interval);
-    let http = reqwest::Client::new();
-
-    let session_store = ctx.clone();
-    let session_store = session_store.lock().await.session.clone();
+    // Disk storage lives under the DID, a random signer would start empty every run.
+    if cmd.rings.rings_storage == RingsStorage::Disk && cmd.from.is_none() && cmd.seed.is_none() {
+        bail!("--rings-storage disk needs a fixed signer, set --from or --seed.");
+    }
+    // Seeded runs use a session derived from the seed and the timestamp as nonce.
+    let mut seeded_session = cmd.seed.map(|_| {
+        let mut id = vec![0u8; 16];
+        rng.fill_bytes(&mut id);
+        id
+    });
+    let mut session_generation = 0;
+    let encr_target = cmd.encr_target()?;
+    let mut sensor: Box<dyn SensorModel> = cmd.sensor.build()?;
 
     macro_rules! tx_send {
         ($msg:expr) => {
@@ -61,28 +154,119 @@ pub async fn run_device_main(
 
     let addr = get_eth_address(&signer.clone().into());
     info!("Signer: {}", &addr);
-    tx_send!(GuiAppMessage::Start(addr));
+    ctx.lock().await.addr = Some(addr.clone());
+    tx_send!(GuiAppMessage::Start(addr.clone()));
+    if let Some(pk) = &encr_target {
+        let recipient = get_eth_address_bytes(&pk.into());
+        info!(
+            "[{}] Encrypting reports to 0x{}",
+            &addr,
+            hex::encode(&recipient)
+        );
+        // Encrypted reports are always addressed to the key they are encrypted to.
+        if report_to.iter().any(|b| *b != 0) && report_to.as_slice() != &recipient[..] {
+            warn!(
+                "[{}] Ignoring --report-to {}, encrypted reports go to 0x{}",
+                &addr,
+                &cmd.report_to,
+                hex::encode(&recipient)
+            );
+        }
+    }
 
     tx_send!(GuiAppMessage::Message(format!(
         "Started with arguments: {:?}",
         &cmd
     )));
 
-    let rings_provider = Provider::create(&signer).await?;
+    let acl = cmd.control.acl()?;
+    if acl.is_open() {
+        warn!("[{}] Any Rings peer can control this device.", &addr);
+    } else if cmd.control.controller.is_empty() && cmd.control.control_acl.is_none() {
+        info!(
+            "[{}] No controller configured, control commands are rejected unless --control-open is set.",
+            &addr
+        );
+    }
+    let rings_provider = Provider::create(&signer, &cmd.rings).await?;
+    if let Some(listen) = cmd.rings.rings_listen {
+        serve_rings_endpoint(rings_provider.clone(), listen).await?;
+    }
+    let bootstrap_urls = cmd.rings_bootstrap_urls();
+    let health = NodeHealth::new(rings_provider.clone(), &addr, &cmd.rings, tx.clone());
+    let bootstrap = BootstrapSupervisor::new(
+        rings_provider.clone(),
+        health.clone(),
+        &addr,
+        &bootstrap_urls,
+        tx.clone(),
+    );
+    {
+        let mut c = ctx.lock().await;
+        c.bootstrap = Some(bootstrap.clone());
+        c.health = Some(health.clone());
+    }
     let rings_handler = BackendBehaviour {
         provider: rings_provider.clone(),
+        addr: addr.clone(),
         ctx: ctx.clone(),
         tx: tx.clone(),
+        acl,
+        signer: signer.clone(),
+        bootstrap: bootstrap.clone(),
     };
     let p_move = rings_provider.clone();
-    p_move.init(
-        &vec![cmd.rings_relay_endpoint.clone()],
-        Arc::new(rings_handler),
-    )?;
+    p_move.init(&bootstrap, &health, Arc::new(rings_handler))?;
+    spawn_service_registration(
+        rings_provider.clone(),
+        bootstrap.clone(),
+        addr.clone(),
+        device_service(&cmd),
+    );
+    if cmd.rings.rings_status_interval > 0 {
+        spawn_status_publisher(
+            rings_provider.clone(),
+            signer.clone(),
+            ctx.clone(),
+            clock.clone(),
+            cmd.interval,
+            Duration::from_secs(cmd.rings.rings_status_interval),
+        );
+    }
+
+    let mut transports = build_transports(
+        &cmd.transport,
+        &cmd,
+        &signer,
+        &addr,
+        Some(rings_provider.clone()),
+    )
+    .await?;
 
+    let mut reports_sent: u64 = 0;
     loop {
-        let weight = ctx.lock().await.weight;
-        let report = OsRng.gen_range(0.1..100.0);
+        let c = ctx.lock().await;
+        let weight = c.weight;
+        let control = c.control.clone();
+        let session_store = c.session.clone();
+        drop(c);
+
+        let d = Duration::from_secs(control.interval.unwrap_or(cmd.interval));
+        let started = Instant::now();
+        let deadline = started + d;
+        if control.paused {
+            clock.advance(d);
+            wait_for_next_report(&ctx, &control, cmd.interval, started).await;
+            continue;
+        }
+        if control.session_generation != session_generation {
+            session_generation = control.session_generation;
+            if let Some(id) = seeded_session.as_mut() {
+                rng.fill_bytes(id);
+            }
+        }
+
+        let report = sensor.read(clock.now(), &mut rng);
         let report = EventData {
             original: report,
             weight,
@@ -93,38 +277,119 @@ pub async fn run_device_main(
 
         let payload = to_vec(&report)?;
 
-        let session = session_store.fetch().await;
+        let (session_id, nonce) = match &seeded_session {
+            Some(id) => (id.clone(), None),
+            None => {
+                let session = session_store.fetch().await;
+                (session.0, Some(session.1))
+            }
+        };
 
-        let (payload, _) = signer
-            .create_message(
-                session.0,
-                Some(session.1),
-                MessageChannel::Normal(233),
+        let channel = match control.channel {
+            Some(c) => MessageChannel::Normal(c),
+            None => channels[(reports_sent % channels.len() as u64) as usize].clone(),
+        };
+
+        let (signed, raw) = signer
+            .create_message_with(
+                session_id,
+                channel,
                 payload,
                 to,
-                None,
+                encr_target,
+                MessageOptions {
+                    nonce,
+                    rng: &mut rng,
+                    clock: clock.as_ref(),
+                    hash_scheme: cmd.hash_scheme,
+                },
             )
             .await?;
-        info!("Fake report: {:?}", &report.weight);
+        info!("[{}] Fake report: {:?}", &addr, &report.weight);
         tx_send!(GuiAppMessage::Message(format!("Published {:?}", &report)));
-        let payload = to_vec(&payload)?;
-        info!("Hex: 0x{}", hex::encode(payload.as_slice()));
-
-        match http
-            .post(cmd.dephy_http_endpoint.as_str())
-            .body(payload)
-            .header("content-type", "application/x-dephy")
-            .send()
-            .await
-        {
-            Ok(res) => {
-                info!("publish message: {}", res.text().await?);
-            }
-            Err(e) => {
-                error!("publish message: {e}");
+        info!("[{}] Hex: 0x{}", &addr, hex::encode(to_vec(&signed)?));
+
+        let mut published = 0;
+        let mut publish_errors = 0;
+        for t in transports.iter_mut() {
+            match t.publish(&signed, &raw).await {
+                Ok(n) => published += n,
+                Err(e) => {
+                    error!("[{}] publish message over {}: {}", &addr, t.name(), e);
+                    publish_errors += 1;
+                }
             }
         }
+        for t in transports.iter_mut() {
+            published += t.flush(deadline).await?;
+        }
 
-        sleep(d).await;
+        let mut c = ctx.lock().await;
+        c.metrics.reports += 1;
+        c.metrics.last_report = Some(report.actually);
+        c.metrics.published += published;
+        c.metrics.publish_errors += publish_errors;
+        drop(c);
+        reports_sent += 1;
+
+        if cmd.count.is_some_and(|count| reports_sent >= count) {
+            info!("[{}] Sent {} reports, stopping.", &addr, reports_sent);
+            return Ok(());
+        }
+
+        clock.advance(d);
+
+        wait_for_next_report(&ctx, &control, cmd.interval, started).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_edge::MockEdge;
+    use clap::Parser;
+
+    /// Encoded messages the edge accepted from a seeded run of three reports.
+    async fn seeded_run(edge: &MockEdge, seed: &str) -> Vec<Vec<u8>> {
+        edge.clear().await;
+        let endpoint = edge.endpoint();
+        let cmd = Cmd::try_parse_from([
+            "simdev",
+            "--seed",
+            seed,
+            "--count",
+            "3",
+            "--interval",
+            "1",
+            "--transport",
+            "http",
+            "--dephy-http-endpoint",
+            &endpoint,
+            "--rings-relay-endpoint",
+            "",
+            "--rings-lan",
+            "--rings-status-interval",
+            "0",
+        ])
+        .unwrap();
+        let ctx = Arc::new(Mutex::new(DeviceContext::default()));
+        run_device_main(cmd, ctx, None).await.unwrap();
+        edge.messages()
+            .await
+            .iter()
+            .map(|m| to_vec(&m.signed).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn seeded_runs_are_reproducible() {
+        let edge = MockEdge::start("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let first = seeded_run(&edge, "7").await;
+        assert_eq!(first.len(), 3);
+        assert_eq!(seeded_run(&edge, "7").await, first);
+        assert_ne!(seeded_run(&edge, "8").await, first);
+        edge.shutdown().await.unwrap();
     }
 }
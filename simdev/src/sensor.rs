@@ -0,0 +1,385 @@
+use crate::preludes::*;
+use anyhow::ensure;
+use clap::{Args, ValueEnum};
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Source of simulated sensor readings.
+pub trait SensorModel: Send {
+    /// Produce the reading for `timestamp` (unix seconds).
+    fn read(&mut self, timestamp: u64, rng: &mut dyn RngCore) -> f64;
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SensorKind {
+    /// Uniformly random value between min and max
+    Random,
+    /// Sine wave between min and max with optional noise
+    Sine,
+    /// Bounded random walk between min and max
+    RandomWalk,
+    /// Alternates between min and max every half period
+    Step,
+    /// Daily solar output curve peaking at max around noon
+    Solar,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct SensorArgs {
+    /// Sensor model used to generate readings
+    #[arg(long, env, value_enum, default_value_t = SensorKind::Random)]
+    pub sensor: SensorKind,
+
+    /// Lower bound of readings
+    #[arg(long, env, default_value_t = 0.1, allow_negative_numbers = true)]
+    pub sensor_min: f64,
+
+    /// Upper bound of readings
+    #[arg(long, env, default_value_t = 100.0, allow_negative_numbers = true)]
+    pub sensor_max: f64,
+
+    /// Period in seconds for the sine and step models
+    #[arg(long, env, default_value_t = 600)]
+    pub sensor_period: u64,
+
+    /// Amplitude of uniform noise added to the sine, step and solar models
+    #[arg(long, env, default_value_t = 0.0)]
+    pub sensor_noise: f64,
+
+    /// Largest change between two readings of the random walk model
+    #[arg(long, env, default_value_t = 1.0)]
+    pub sensor_step: f64,
+
+    /// UTC offset in hours used by the solar model to find local noon
+    #[arg(long, env, default_value_t = 0, allow_negative_numbers = true)]
+    pub sensor_utc_offset: i64,
+}
+
+impl SensorArgs {
+    pub fn build(&self) -> Result<Box<dyn SensorModel>> {
+        // Infinite bounds would make `gen_range` panic on the first reading.
+        ensure!(
+            self.sensor_min.is_finite() && self.sensor_max.is_finite(),
+            "sensor_min and sensor_max should be finite: min={} max={}",
+            self.sensor_min,
+            self.sensor_max
+        );
+        ensure!(
+            self.sensor_min < self.sensor_max,
+            "sensor_min should be less than sensor_max: min={} max={}",
+            self.sensor_min,
+            self.sensor_max
+        );
+        ensure!(
+            self.sensor_noise >= 0.0 && self.sensor_noise.is_finite(),
+            "sensor_noise should be finite and not negative"
+        );
+        let model: Box<dyn SensorModel> = match self.sensor {
+            SensorKind::Random => Box::new(RandomModel {
+                min: self.sensor_min,
+                max: self.sensor_max,
+            }),
+            SensorKind::Sine => {
+                ensure!(self.sensor_period > 0, "sensor_period should not be zero");
+                Box::new(SineModel {
+                    min: self.sensor_min,
+                    max: self.sensor_max,
+                    period: self.sensor_period,
+                    noise: self.sensor_noise,
+                })
+            }
+            SensorKind::RandomWalk => {
+                ensure!(
+                    self.sensor_step > 0.0 && self.sensor_step.is_finite(),
+                    "sensor_step should be finite and positive"
+                );
+                Box::new(RandomWalkModel {
+                    min: self.sensor_min,
+                    max: self.sensor_max,
+                    step: self.sensor_step,
+                    current: None,
+                })
+            }
+            SensorKind::Step => {
+                ensure!(self.sensor_period > 0, "sensor_period should not be zero");
+                Box::new(StepModel {
+                    low: self.sensor_min,
+                    high: self.sensor_max,
+                    period: self.sensor_period,
+                    noise: self.sensor_noise,
+                })
+            }
+            SensorKind::Solar => Box::new(SolarModel {
+                min: self.sensor_min,
+                peak: self.sensor_max,
+                utc_offset: self.sensor_utc_offset,
+                noise: self.sensor_noise,
+            }),
+        };
+        Ok(model)
+    }
+}
+
+fn noise(rng: &mut dyn RngCore, amplitude: f64) -> f64 {
+    if amplitude > 0.0 {
+        rng.gen_range(-amplitude..=amplitude)
+    } else {
+        0.0
+    }
+}
+
+pub struct RandomModel {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl SensorModel for RandomModel {
+    fn read(&mut self, _timestamp: u64, rng: &mut dyn RngCore) -> f64 {
+        rng.gen_range(self.min..self.max)
+    }
+}
+
+pub struct SineModel {
+    pub min: f64,
+    pub max: f64,
+    pub period: u64,
+    pub noise: f64,
+}
+
+impl SensorModel for SineModel {
+    fn read(&mut self, timestamp: u64, rng: &mut dyn RngCore) -> f64 {
+        let mid = (self.min + self.max) / 2.0;
+        let amplitude = (self.max - self.min) / 2.0;
+        let phase = (timestamp % self.period) as f64 / self.period as f64;
+        mid + amplitude * (2.0 * PI * phase).sin() + noise(rng, self.noise)
+    }
+}
+
+pub struct RandomWalkModel {
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+    pub current: Option<f64>,
+}
+
+impl SensorModel for RandomWalkModel {
+    fn read(&mut self, _timestamp: u64, rng: &mut dyn RngCore) -> f64 {
+        let next = match self.current {
+            None => rng.gen_range(self.min..self.max),
+            Some(c) => (c + rng.gen_range(-self.step..=self.step)).clamp(self.min, self.max),
+        };
+        self.current = Some(next);
+        next
+    }
+}
+
+pub struct StepModel {
+    pub low: f64,
+    pub high: f64,
+    pub period: u64,
+    pub noise: f64,
+}
+
+impl SensorModel for StepModel {
+    fn read(&mut self, timestamp: u64, rng: &mut dyn RngCore) -> f64 {
+        let level = if (timestamp % self.period) * 2 < self.period {
+            self.low
+        } else {
+            self.high
+        };
+        level + noise(rng, self.noise)
+    }
+}
+
+pub struct SolarModel {
+    pub min: f64,
+    pub peak: f64,
+    pub utc_offset: i64,
+    pub noise: f64,
+}
+
+impl SensorModel for SolarModel {
+    fn read(&mut self, timestamp: u64, rng: &mut dyn RngCore) -> f64 {
+        // Daylight from 06:00 to 18:00 local time, following half a sine period.
+        let local = (timestamp as i64 + self.utc_offset * 3600).rem_euclid(SECONDS_PER_DAY);
+        let hour = local as f64 / 3600.0;
+        if !(6.0..18.0).contains(&hour) {
+            return self.min;
+        }
+        let curve = (PI * (hour - 6.0) / 12.0).sin();
+        let value = self.min + (self.peak - self.min) * curve + noise(rng, self.noise);
+        value.clamp(self.min, self.peak)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimRng, DEFAULT_SIM_EPOCH};
+
+    static EPSILON: f64 = 1e-9;
+
+    fn args(sensor: SensorKind) -> SensorArgs {
+        SensorArgs {
+            sensor,
+            sensor_min: 10.0,
+            sensor_max: 20.0,
+            sensor_period: 600,
+            sensor_noise: 0.0,
+            sensor_step: 1.0,
+            sensor_utc_offset: 0,
+        }
+    }
+
+    /// Readings every `every` seconds over `span` seconds from `DEFAULT_SIM_EPOCH`.
+    fn readings(args: &SensorArgs, span: u64, every: u64) -> Vec<(u64, f64)> {
+        let mut model = args.build().unwrap();
+        let mut rng = SimRng::new(Some(1));
+        (DEFAULT_SIM_EPOCH..DEFAULT_SIM_EPOCH + span)
+            .step_by(every as usize)
+            .map(|t| (t, model.read(t, &mut rng)))
+            .collect()
+    }
+
+    fn assert_bounded(readings: &[(u64, f64)], min: f64, max: f64) {
+        for (t, v) in readings {
+            assert!(
+                (min - EPSILON..=max + EPSILON).contains(v),
+                "reading {} at {} out of {}..={}",
+                v,
+                t,
+                min,
+                max
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_args() {
+        let mut a = args(SensorKind::Random);
+        a.sensor_min = 20.0;
+        assert!(a.build().is_err());
+
+        let mut a = args(SensorKind::Sine);
+        a.sensor_noise = -1.0;
+        assert!(a.build().is_err());
+
+        let mut a = args(SensorKind::Step);
+        a.sensor_period = 0;
+        assert!(a.build().is_err());
+
+        let mut a = args(SensorKind::RandomWalk);
+        a.sensor_step = 0.0;
+        assert!(a.build().is_err());
+
+        for value in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            for kind in [
+                SensorKind::Random,
+                SensorKind::RandomWalk,
+                SensorKind::Solar,
+            ] {
+                let mut a = args(kind);
+                a.sensor_min = value;
+                assert!(a.build().is_err(), "{:?} min={}", kind, value);
+                let mut a = args(kind);
+                a.sensor_max = value;
+                assert!(a.build().is_err(), "{:?} max={}", kind, value);
+                let mut a = args(kind);
+                a.sensor_noise = value;
+                assert!(a.build().is_err(), "{:?} noise={}", kind, value);
+            }
+            let mut a = args(SensorKind::RandomWalk);
+            a.sensor_step = value;
+            assert!(a.build().is_err(), "step={}", value);
+        }
+    }
+
+    #[test]
+    fn random_stays_in_range() {
+        assert_bounded(&readings(&args(SensorKind::Random), 1000, 1), 10.0, 20.0);
+    }
+
+    #[test]
+    fn sine_follows_period() {
+        let a = args(SensorKind::Sine);
+        let r = readings(&a, 1200, 1);
+        assert_bounded(&r, 10.0, 20.0);
+        let at = |offset: u64| {
+            let start = DEFAULT_SIM_EPOCH - DEFAULT_SIM_EPOCH % 600;
+            let mut model = a.build().unwrap();
+            model.read(start + offset, &mut SimRng::new(Some(1)))
+        };
+        assert!((at(0) - 15.0).abs() < EPSILON);
+        assert!((at(150) - 20.0).abs() < EPSILON);
+        assert!((at(300) - 15.0).abs() < EPSILON);
+        assert!((at(450) - 10.0).abs() < EPSILON);
+        assert!((at(600) - at(0)).abs() < EPSILON);
+    }
+
+    #[test]
+    fn sine_noise_is_bounded() {
+        let mut a = args(SensorKind::Sine);
+        a.sensor_noise = 2.0;
+        let r = readings(&a, 1200, 1);
+        assert_bounded(&r, 8.0, 22.0);
+        assert!(r.iter().any(|(_, v)| *v > 20.0 || *v < 10.0));
+    }
+
+    #[test]
+    fn step_alternates_every_half_period() {
+        let r = readings(&args(SensorKind::Step), 1200, 1);
+        for (t, v) in r {
+            let expected = match t % 600 < 300 {
+                true => 10.0,
+                false => 20.0,
+            };
+            assert_eq!(v, expected, "reading at {}", t);
+        }
+    }
+
+    #[test]
+    fn random_walk_is_bounded_and_continuous() {
+        let mut a = args(SensorKind::RandomWalk);
+        a.sensor_step = 3.0;
+        let r = readings(&a, 5000, 1);
+        assert_bounded(&r, 10.0, 20.0);
+        for w in r.windows(2) {
+            assert!((w[1].1 - w[0].1).abs() <= 3.0 + EPSILON);
+        }
+        // Clamping at the bounds must not pin the walk there.
+        assert!(r.iter().any(|(_, v)| *v < 12.0));
+        assert!(r.iter().any(|(_, v)| *v > 18.0));
+    }
+
+    #[test]
+    fn solar_peaks_at_local_noon() {
+        let day = DEFAULT_SIM_EPOCH - DEFAULT_SIM_EPOCH % 86400;
+        for offset in [-5i64, 0, 8] {
+            let mut a = args(SensorKind::Solar);
+            a.sensor_utc_offset = offset;
+            let mut model = a.build().unwrap();
+            let mut rng = SimRng::new(Some(1));
+            // UTC time of `hour` local time on `day`
+            let at = |hour: i64| (day as i64 + (hour - offset) * 3600) as u64;
+            let mut read = |hour: i64| model.read(at(hour), &mut rng);
+
+            assert_eq!(read(3), 10.0);
+            assert_eq!(read(5), 10.0);
+            assert_eq!(read(18), 10.0);
+            assert_eq!(read(23), 10.0);
+            assert!((read(12) - 20.0).abs() < EPSILON);
+            assert!((read(9) - read(15)).abs() < EPSILON);
+            assert!(read(9) < read(11));
+            assert!(read(13) > read(17));
+        }
+    }
+
+    #[test]
+    fn solar_noise_stays_in_range() {
+        let mut a = args(SensorKind::Solar);
+        a.sensor_noise = 5.0;
+        assert_bounded(&readings(&a, 2 * 86400, 60), 10.0, 20.0);
+    }
+}
@@ -0,0 +1,96 @@
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub static DEFAULT_SIM_EPOCH: u64 = 1700000000;
+
+/// Source of randomness for a simulated device, seeded runs are reproducible.
+pub enum SimRng {
+    Os(OsRng),
+    Seeded(Box<ChaCha20Rng>),
+}
+
+impl SimRng {
+    pub fn new(seed: Option<u64>) -> Self {
+        match seed {
+            Some(seed) => Self::Seeded(Box::new(ChaCha20Rng::seed_from_u64(seed))),
+            None => Self::Os(OsRng),
+        }
+    }
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Self::Os(r) => r.next_u32(),
+            Self::Seeded(r) => r.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Self::Os(r) => r.next_u64(),
+            Self::Seeded(r) => r.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Self::Os(r) => r.fill_bytes(dest),
+            Self::Seeded(r) => r.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            Self::Os(r) => r.try_fill_bytes(dest),
+            Self::Seeded(r) => r.try_fill_bytes(dest),
+        }
+    }
+}
+
+impl CryptoRng for SimRng {}
+
+/// Time source used for readings and message timestamps.
+pub trait Clock: Send + Sync {
+    /// Current unix time in seconds.
+    fn now(&self) -> u64;
+    /// Called after each report, simulated clocks move forward by `d`.
+    fn advance(&self, _d: Duration) {}
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+/// Clock that only moves when advanced, starting at a fixed epoch.
+pub struct SteppedClock {
+    now: AtomicU64,
+}
+
+impl SteppedClock {
+    pub fn new(epoch: u64) -> Self {
+        Self {
+            now: AtomicU64::new(epoch),
+        }
+    }
+}
+
+impl Clock for SteppedClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    fn advance(&self, d: Duration) {
+        self.now.fetch_add(d.as_secs(), Ordering::SeqCst);
+    }
+}
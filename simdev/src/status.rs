@@ -0,0 +1,217 @@
+use crate::control::normalize_did;
+use crate::preludes::*;
+use crate::report::DeviceContext;
+use crate::sim::{Clock, SystemClock};
+use anyhow::ensure;
+use rings_node::provider::Provider;
+use rings_rpc::method::Method;
+use rings_rpc::protos::rings_node::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+pub static STATUS_RECORD_VERSION: u32 = 1;
+
+/// Seconds covered by one status topic.
+pub static STATUS_TOPIC_PERIOD: u64 = 3600;
+/// Shortest time between two publications, bounds a topic to 360 records of its device.
+pub static STATUS_MIN_INTERVAL: Duration = Duration::from_secs(10);
+/// Newest entries of a topic checked when fetching.
+pub static STATUS_FETCH_LIMIT: usize = 64;
+
+/// DHT topic holding the status records a device published around `timestamp`.
+///
+/// Topics only grow, so records go to a new topic every `STATUS_TOPIC_PERIOD`.
+pub fn status_topic(did: &str, timestamp: u64) -> String {
+    format!(
+        "dephy-simdev/status/{}/{}",
+        normalize_did(did),
+        timestamp / STATUS_TOPIC_PERIOD
+    )
+}
+
+/// Device state published to the Rings DHT.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StatusRecord {
+    pub did: String,
+    pub weight: f64,
+    /// Seconds between reports
+    pub interval: u64,
+    pub paused: bool,
+    pub last_report: Option<f64>,
+    /// Seconds since the device started
+    pub uptime: u64,
+    /// Version of simdev
+    pub software: String,
+    /// Unix time in seconds of publication
+    pub timestamp: u64,
+}
+
+impl StatusRecord {
+    pub fn of(
+        ctx: &DeviceContext,
+        did: &str,
+        interval: u64,
+        uptime: Duration,
+        clock: &dyn Clock,
+    ) -> Self {
+        Self {
+            did: normalize_did(did),
+            weight: ctx.weight,
+            interval: ctx.control.interval.unwrap_or(interval),
+            paused: ctx.control.paused,
+            last_report: ctx.metrics.last_report,
+            uptime: uptime.as_secs(),
+            software: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: clock.now(),
+        }
+    }
+}
+
+/// `StatusRecord` as JSON with a signature by the device key.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SignedStatusRecord {
+    pub version: u32,
+    pub record: String,
+    /// `0x` hex of `r || s || v`
+    pub signature: String,
+}
+
+impl SignedStatusRecord {
+    pub fn sign(record: &StatusRecord, key: &SigningKey) -> Result<Self> {
+        let record = serde_json::to_string(record)?;
+        Ok(Self {
+            version: STATUS_RECORD_VERSION,
+            signature: sign_text(key, &record)?,
+            record,
+        })
+    }
+
+    /// Check that the record was signed by the key of `did` and is about `did`.
+    pub fn verify(&self, did: &str) -> Result<StatusRecord> {
+        ensure!(
+            self.version == STATUS_RECORD_VERSION,
+            "Unsupported status record version {}, expected {}",
+            self.version,
+            STATUS_RECORD_VERSION
+        );
+        let did = normalize_did(did);
+        let signer = recover_text_signer(&self.record, &self.signature)?;
+        ensure!(
+            signer == did,
+            "Status record signed by {} instead of {}",
+            signer,
+            did
+        );
+        let record: StatusRecord = serde_json::from_str(&self.record)?;
+        ensure!(
+            normalize_did(&record.did) == did,
+            "Status record of {} published for {}",
+            record.did,
+            did
+        );
+        Ok(record)
+    }
+}
+
+pub async fn publish_status(
+    provider: &Provider,
+    key: &SigningKey,
+    record: &StatusRecord,
+) -> Result<()> {
+    let data = serde_json::to_string(&SignedStatusRecord::sign(record, key)?)?;
+    provider
+        .request(
+            Method::PublishMessageToTopic,
+            PublishMessageToTopicRequest {
+                topic: status_topic(&record.did, record.timestamp),
+                data,
+            },
+        )
+        .await
+        .map_err(|e| anyhow!("{}", e))?;
+    Ok(())
+}
+
+/// Newest status record of `did` in the DHT carrying a valid signature of `did`.
+pub async fn fetch_device_status(provider: &Provider, did: &str) -> Result<StatusRecord> {
+    fetch_device_status_with(provider, did, &SystemClock).await
+}
+
+/// Same as `fetch_device_status`, for devices publishing with `clock`.
+///
+/// Looks in the topics of the current and previous periods. Anyone can write to a topic,
+/// so only its newest `STATUS_FETCH_LIMIT` entries are checked and forged ones skipped.
+pub async fn fetch_device_status_with(
+    provider: &Provider,
+    did: &str,
+    clock: &dyn Clock,
+) -> Result<StatusRecord> {
+    let now = clock.now();
+    for timestamp in [now, now.saturating_sub(STATUS_TOPIC_PERIOD)] {
+        let resp = provider
+            .request(
+                Method::FetchTopicMessages,
+                FetchTopicMessagesRequest {
+                    topic: status_topic(did, timestamp),
+                    skip: 0,
+                },
+            )
+            .await
+            .map_err(|e| anyhow!("{}", e))?;
+        let resp: FetchTopicMessagesResponse = serde_json::from_value(resp)?;
+        let record = resp
+            .data
+            .iter()
+            .rev()
+            .take(STATUS_FETCH_LIMIT)
+            .find_map(|item| {
+                let record = serde_json::from_str::<SignedStatusRecord>(item)
+                    .map_err(|e| anyhow!("{}", e))
+                    .and_then(|r| r.verify(did));
+                match record {
+                    Ok(r) => Some(r),
+                    Err(e) => {
+                        debug!("Ignoring status record of {}: {}", did, e);
+                        None
+                    }
+                }
+            });
+        if let Some(record) = record {
+            return Ok(record);
+        }
+    }
+    bail!("No valid status record of {} found.", did)
+}
+
+/// Publish the status of the device every `every`, at least `STATUS_MIN_INTERVAL`.
+/// Failures are logged and retried next time.
+pub fn spawn_status_publisher(
+    provider: Arc<Provider>,
+    key: SigningKey,
+    ctx: Arc<Mutex<DeviceContext>>,
+    clock: Arc<dyn Clock>,
+    interval: u64,
+    every: Duration,
+) {
+    let started = Instant::now();
+    let did = get_eth_address(&key.clone().into());
+    let every = every.max(STATUS_MIN_INTERVAL);
+    tokio::spawn(async move {
+        loop {
+            let record = StatusRecord::of(
+                &*ctx.lock().await,
+                &did,
+                interval,
+                started.elapsed(),
+                clock.as_ref(),
+            );
+            match publish_status(&provider, &key, &record).await {
+                Ok(_) => debug!("[{}] Published status to the DHT", &did),
+                Err(e) => warn!("[{}] Failed to publish status: {}", &did, e),
+            }
+            tokio::time::sleep(every).await;
+        }
+    });
+}
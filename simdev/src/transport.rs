@@ -0,0 +1,219 @@
+use crate::mqtt::MqttTransport;
+use crate::nostr::NostrTransport;
+use crate::preludes::*;
+use crate::queue::{Backoff, OfflineQueue, QueueArgs};
+use crate::rings::RingsTransport;
+use async_trait::async_trait;
+use borsh::to_vec;
+use clap::ValueEnum;
+use rand::rngs::OsRng;
+use rings_node::provider::Provider;
+use std::sync::Arc;
+use tokio::time::{sleep_until, Instant};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportKind {
+    /// POST to the DePHY edge HTTP endpoint
+    Http,
+    /// Publish to DEPHY_TOPIC on an MQTT broker
+    Mqtt,
+    /// Publish DePHY events to Nostr relays
+    Nostr,
+    /// Send to a Rings DID as a backend message
+    Rings,
+}
+
+/// A channel that signed messages can be published over.
+#[async_trait]
+pub trait Transport: Send {
+    fn name(&self) -> &'static str;
+
+    /// Publish one message, returns how many messages were delivered.
+    ///
+    /// Transports keeping a queue may deliver earlier messages along with this one.
+    async fn publish(&mut self, msg: &SignedMessage, raw: &RawMessage) -> Result<u64>;
+
+    /// Retry pending messages until `deadline`, returns how many were delivered.
+    async fn flush(&mut self, _deadline: Instant) -> Result<u64> {
+        Ok(0)
+    }
+}
+
+pub async fn build_transports(
+    selected: &[TransportKind],
+    cmd: &Cmd,
+    signer: &SigningKey,
+    addr: &str,
+    rings_provider: Option<Arc<Provider>>,
+) -> Result<Vec<Box<dyn Transport>>> {
+    let mut kinds: Vec<TransportKind> = vec![];
+    for kind in selected.iter() {
+        if !kinds.contains(kind) {
+            kinds.push(*kind);
+        }
+    }
+
+    let mut ret: Vec<Box<dyn Transport>> = vec![];
+    for kind in kinds {
+        let t: Box<dyn Transport> = match kind {
+            TransportKind::Http => Box::new(HttpTransport::new(
+                addr,
+                cmd.dephy_http_endpoint.as_str(),
+                &cmd.queue,
+            )?),
+            TransportKind::Mqtt => Box::new(MqttTransport::connect(&cmd.mqtt, addr)?),
+            TransportKind::Nostr => Box::new(NostrTransport::connect(&cmd.nostr, signer).await?),
+            TransportKind::Rings => Box::new(RingsTransport::new(
+                rings_provider
+                    .clone()
+                    .ok_or(anyhow!("Rings transport is not available here."))?,
+                cmd.rings_destination.clone(),
+            )?),
+        };
+        info!("[{}] Publishing over {}", addr, t.name());
+        ret.push(t);
+    }
+    if ret.is_empty() {
+        bail!("At least one transport should be selected.");
+    }
+    Ok(ret)
+}
+
+enum PostOutcome {
+    Delivered(String),
+    /// The edge refused the message, retrying would not help.
+    Rejected(String),
+    /// Network or server error, worth retrying later.
+    Failed(String),
+}
+
+async fn post_message(http: &reqwest::Client, endpoint: &str, payload: Vec<u8>) -> PostOutcome {
+    match http
+        .post(endpoint)
+        .body(payload)
+        .header("content-type", "application/x-dephy")
+        .send()
+        .await
+    {
+        Ok(res) => {
+            let status = res.status();
+            let text = res.text().await.unwrap_or_default();
+            if status.is_success() {
+                PostOutcome::Delivered(text)
+            } else if status.is_client_error() {
+                PostOutcome::Rejected(format!("{} {}", status, text))
+            } else {
+                PostOutcome::Failed(format!("{} {}", status, text))
+            }
+        }
+        Err(e) => PostOutcome::Failed(e.to_string()),
+    }
+}
+
+pub struct HttpTransport {
+    addr: String,
+    http: reqwest::Client,
+    endpoint: String,
+    queue: Option<OfflineQueue>,
+    backoff: Backoff,
+}
+
+impl HttpTransport {
+    pub fn new(addr: &str, endpoint: &str, queue_args: &QueueArgs) -> Result<Self> {
+        Ok(Self {
+            addr: addr.to_string(),
+            http: reqwest::Client::new(),
+            endpoint: endpoint.to_string(),
+            queue: queue_args.open(addr)?,
+            backoff: queue_args.backoff(),
+        })
+    }
+
+    /// Deliver queued messages in order until the queue is empty or a delivery fails.
+    async fn drain(&mut self) -> Result<u64> {
+        let addr = self.addr.as_str();
+        let Some(queue) = self.queue.as_mut() else {
+            return Ok(0);
+        };
+        let mut delivered = 0;
+        while let Some(msg) = queue.front()? {
+            match post_message(&self.http, &self.endpoint, msg).await {
+                PostOutcome::Delivered(t) => {
+                    info!("[{}] publish message: {}", addr, t);
+                    queue.pop()?;
+                    self.backoff.reset();
+                    delivered += 1;
+                }
+                PostOutcome::Rejected(e) => {
+                    error!("[{}] publish message rejected, dropping: {}", addr, e);
+                    queue.pop()?;
+                }
+                PostOutcome::Failed(e) => {
+                    let delay = self.backoff.fail(&mut OsRng);
+                    bail!("{}, {} queued, retrying in {:?}", e, queue.len(), delay);
+                }
+            }
+        }
+        Ok(delivered)
+    }
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    async fn publish(&mut self, msg: &SignedMessage, _raw: &RawMessage) -> Result<u64> {
+        let payload = to_vec(msg)?;
+        let Some(queue) = self.queue.as_mut() else {
+            return match post_message(&self.http, &self.endpoint, payload).await {
+                PostOutcome::Delivered(t) => {
+                    info!("[{}] publish message: {}", &self.addr, t);
+                    Ok(1)
+                }
+                PostOutcome::Rejected(e) | PostOutcome::Failed(e) => bail!(e),
+            };
+        };
+        // Earlier messages go first, queue behind them while the edge is away.
+        if !queue.is_empty() || !self.backoff.is_ready() {
+            queue.push(&payload)?;
+            if !self.backoff.is_ready() {
+                bail!("edge unavailable, {} queued", queue.len());
+            }
+            return self.drain().await;
+        }
+        match post_message(&self.http, &self.endpoint, payload.clone()).await {
+            PostOutcome::Delivered(t) => {
+                info!("[{}] publish message: {}", &self.addr, t);
+                self.backoff.reset();
+                Ok(1)
+            }
+            PostOutcome::Rejected(e) => bail!(e),
+            PostOutcome::Failed(e) => {
+                queue.push(&payload)?;
+                let delay = self.backoff.fail(&mut OsRng);
+                bail!("{}, {} queued, retrying in {:?}", e, queue.len(), delay);
+            }
+        }
+    }
+
+    async fn flush(&mut self, deadline: Instant) -> Result<u64> {
+        let mut delivered = 0;
+        loop {
+            let pending = match &self.queue {
+                Some(queue) => !queue.is_empty(),
+                None => false,
+            };
+            if !pending || self.backoff.next_try() >= deadline {
+                break;
+            }
+            sleep_until(self.backoff.next_try()).await;
+            match self.drain().await {
+                Ok(n) => delivered += n,
+                Err(e) => error!("[{}] publish message: {}", &self.addr, e),
+            }
+        }
+        Ok(delivered)
+    }
+}
@@ -0,0 +1,264 @@
+use crate::preludes::*;
+use crate::sim::{SimRng, SteppedClock, DEFAULT_SIM_EPOCH};
+use anyhow::ensure;
+use borsh::to_vec;
+use clap::{Args, ValueEnum};
+use k256::PublicKey;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub static VECTORS_MANIFEST: &str = "vectors.json";
+
+#[derive(Args, Clone, Debug)]
+pub struct VectorsArgs {
+    /// Directory holding the test vectors
+    #[arg(long, default_value = "test-vectors")]
+    pub dir: PathBuf,
+
+    /// Write the vectors instead of verifying them
+    #[arg(long)]
+    pub generate: bool,
+}
+
+/// Expected values of one signed message stored as `<name>.hex` next to the manifest.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TestVector {
+    pub name: String,
+    pub hash_scheme: String,
+    pub hash: String,
+    pub from: String,
+    pub to: String,
+    pub signer_public_key: String,
+    pub session_id: String,
+    pub nonce: u64,
+    pub channel: u8,
+    pub encrypted: bool,
+    /// Plain payload, before encryption for encrypted vectors
+    pub payload: String,
+    /// Private key able to decrypt the payload of encrypted vectors
+    pub recipient_key: Option<String>,
+}
+
+struct VectorCase {
+    name: &'static str,
+    seed: u64,
+    scheme: HashScheme,
+    channel: u8,
+    to: Option<[u8; 20]>,
+    encrypted: bool,
+    payload: &'static [u8],
+}
+
+static VECTOR_CASES: &[VectorCase] = &[
+    VectorCase {
+        name: "v1_plain",
+        seed: 1,
+        scheme: HashScheme::V1,
+        channel: 233,
+        to: None,
+        encrypted: false,
+        payload: b"simdev test vector",
+    },
+    VectorCase {
+        name: "v1_addressed",
+        seed: 2,
+        scheme: HashScheme::V1,
+        channel: 7,
+        to: Some([0x11; 20]),
+        encrypted: false,
+        payload: b"",
+    },
+    VectorCase {
+        name: "v1_encrypted",
+        seed: 3,
+        scheme: HashScheme::V1,
+        channel: 233,
+        to: None,
+        encrypted: true,
+        payload: b"encrypted simdev test vector",
+    },
+    VectorCase {
+        name: "legacy_borsh_nonce",
+        seed: 4,
+        scheme: HashScheme::LegacyBorshNonce,
+        channel: 233,
+        to: None,
+        encrypted: false,
+        payload: b"simdev test vector",
+    },
+];
+
+fn scheme_name(scheme: HashScheme) -> Result<String> {
+    Ok(scheme
+        .to_possible_value()
+        .ok_or(anyhow!("Unnamed hash scheme {:?}", scheme))?
+        .get_name()
+        .to_string())
+}
+
+fn hex0x<T: AsRef<[u8]>>(data: T) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+/// Build a vector, everything random comes from the case seed so the output never changes.
+async fn build_vector(case: &VectorCase) -> Result<(TestVector, Vec<u8>)> {
+    let mut rng = SimRng::new(Some(case.seed));
+    let clock = SteppedClock::new(DEFAULT_SIM_EPOCH);
+    let signer = random_signing_key_with(&mut rng);
+    let recipient = random_signing_key_with(&mut rng);
+    let mut session_id = vec![0u8; 16];
+    rng.fill_bytes(&mut session_id);
+
+    let encr_target = if case.encrypted {
+        Some(PublicKey::from(recipient.verifying_key()))
+    } else {
+        None
+    };
+    let (signed, raw) = signer
+        .create_message_with(
+            session_id.clone(),
+            MessageChannel::Normal(case.channel),
+            case.payload.to_vec(),
+            case.to.map(|t| t.to_vec()),
+            encr_target,
+            MessageOptions {
+                nonce: None,
+                rng: &mut rng,
+                clock: &clock,
+                hash_scheme: case.scheme,
+            },
+        )
+        .await?;
+
+    let vector = TestVector {
+        name: case.name.to_string(),
+        hash_scheme: scheme_name(case.scheme)?,
+        hash: hex0x(&signed.hash),
+        from: hex0x(&raw.from_address),
+        to: hex0x(&raw.to_address),
+        signer_public_key: hex0x(signer.verifying_key().to_sec1_bytes()),
+        session_id: hex0x(&session_id),
+        nonce: signed.nonce,
+        channel: case.channel,
+        encrypted: case.encrypted,
+        payload: hex0x(case.payload),
+        recipient_key: case.encrypted.then(|| hex0x(recipient.to_bytes())),
+    };
+    Ok((vector, to_vec(&signed)?))
+}
+
+fn vector_file(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.hex", name))
+}
+
+pub async fn generate_vectors(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut vectors = vec![];
+    for case in VECTOR_CASES.iter() {
+        let (vector, message) = build_vector(case).await?;
+        fs::write(vector_file(dir, case.name), format!("{}\n", hex0x(message)))?;
+        vectors.push(vector);
+    }
+    let manifest = serde_json::to_string_pretty(&vectors)?;
+    fs::write(dir.join(VECTORS_MANIFEST), format!("{}\n", manifest))?;
+    info!("Wrote {} test vectors to {}", vectors.len(), dir.display());
+    Ok(())
+}
+
+fn verify_vector(vector: &TestVector, message: &[u8]) -> Result<()> {
+    let scheme = HashScheme::from_str(&vector.hash_scheme, true).map_err(|e| anyhow!(e))?;
+    let (signed, raw, matched) = check_message_with(message, &[scheme])?;
+    ensure!(matched == scheme, "Matched scheme {:?}", matched);
+    ensure!(hex0x(&signed.hash) == vector.hash, "Hash mismatch");
+    ensure!(hex0x(&raw.from_address) == vector.from, "From mismatch");
+    ensure!(hex0x(&raw.to_address) == vector.to, "To mismatch");
+    ensure!(
+        hex0x(&signed.session_id) == vector.session_id,
+        "Session mismatch"
+    );
+    ensure!(signed.nonce == vector.nonce, "Nonce mismatch");
+    ensure!(
+        raw.encrypted == vector.encrypted,
+        "Encryption flag mismatch"
+    );
+
+    let signer = recover_message_signer(&signed)?;
+    ensure!(
+        hex0x(signer.to_sec1_bytes()) == vector.signer_public_key,
+        "Signer public key mismatch"
+    );
+
+    let payload = match &vector.recipient_key {
+        Some(key) => {
+            let key = parse_signing_key(key.trim_start_matches("0x"))?;
+            decrypt_message(&key, &raw, &PublicKey::from(&signer))?
+        }
+        None => raw.payload.clone(),
+    };
+    ensure!(hex0x(payload) == vector.payload, "Payload mismatch");
+
+    if scheme != CURRENT_HASH_SCHEME {
+        ensure!(
+            check_message(message).is_err(),
+            "Legacy message accepted by check_message"
+        );
+    }
+    Ok(())
+}
+
+/// Check the stored vectors, and that simdev still produces them byte for byte.
+pub async fn verify_vectors(dir: &Path) -> Result<()> {
+    let manifest = fs::read_to_string(dir.join(VECTORS_MANIFEST))?;
+    let vectors: Vec<TestVector> = serde_json::from_str(&manifest)?;
+    ensure!(!vectors.is_empty(), "No test vector in {}", dir.display());
+
+    let mut failed = 0;
+    for vector in vectors.iter() {
+        let path = vector_file(dir, &vector.name);
+        let message = decode_message_str(&fs::read_to_string(&path)?)?;
+        let mut res = verify_vector(vector, &message);
+        if res.is_ok() {
+            if let Some(case) = VECTOR_CASES.iter().find(|c| c.name == vector.name) {
+                let (expected, expected_message) = build_vector(case).await?;
+                if expected != *vector || expected_message != message {
+                    res = Err(anyhow!("Output of create_message changed"));
+                }
+            }
+        }
+        match res {
+            Ok(()) => println!("ok     {}", &vector.name),
+            Err(e) => {
+                println!("FAILED {}: {}", &vector.name, e);
+                failed += 1;
+            }
+        }
+    }
+    ensure!(
+        failed == 0,
+        "{} of {} test vectors failed",
+        failed,
+        vectors.len()
+    );
+    Ok(())
+}
+
+pub async fn run_vectors_main(args: VectorsArgs) -> Result<()> {
+    if args.generate {
+        generate_vectors(&args.dir).await
+    } else {
+        verify_vectors(&args.dir).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn shipped_vectors_verify() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-vectors");
+        verify_vectors(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+use crate::preludes::*;
+use crate::sim::{Clock, SystemClock};
+use anyhow::ensure;
+use clap::Args;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+#[derive(Args, Clone, Debug)]
+pub struct VerifierArgs {
+    /// Maximum age in seconds of an accepted message, keep it above --queue-max-age of devices
+    #[arg(long, env, default_value_t = 86400)]
+    pub verify_max_age: u64,
+
+    /// Seconds a message timestamp may be ahead of the local clock
+    #[arg(long, env, default_value_t = 60)]
+    pub verify_max_skew: u64,
+
+    /// Message hashes remembered per sender to detect replays
+    #[arg(long, env, default_value_t = 4096)]
+    pub verify_cache_size: usize,
+
+    /// Senders tracked at once, the least recently seen is forgotten first. The replay
+    /// floors of as many forgotten senders are kept.
+    #[arg(long, env, default_value_t = 10000)]
+    pub verify_max_senders: usize,
+}
+
+/// Recently accepted messages of one sender.
+#[derive(Default)]
+struct SenderHistory {
+    hashes: HashSet<Vec<u8>>,
+    /// `(timestamp, hash)` in the order accepted
+    order: VecDeque<(u64, Vec<u8>)>,
+    /// Newest timestamp dropped from the cache while still fresh, anything at or
+    /// before it may be a replay that the cache can no longer tell apart.
+    floor: u64,
+    last_seen: u64,
+}
+
+impl SenderHistory {
+    fn prune(&mut self, oldest: u64) {
+        while let Some((ts, _)) = self.order.front() {
+            if *ts >= oldest {
+                break;
+            }
+            let (_, hash) = self.order.pop_front().unwrap();
+            self.hashes.remove(&hash);
+        }
+    }
+
+    /// Newest timestamp the sender could replay if its history were forgotten.
+    fn newest(&self) -> u64 {
+        self.order
+            .iter()
+            .map(|(ts, _)| *ts)
+            .fold(self.floor, u64::max)
+    }
+
+    fn insert(&mut self, timestamp: u64, hash: Vec<u8>, max_items: usize) {
+        self.hashes.insert(hash.clone());
+        self.order.push_back((timestamp, hash));
+        while self.order.len() > max_items {
+            let (ts, hash) = self.order.pop_front().unwrap();
+            self.hashes.remove(&hash);
+            self.floor = self.floor.max(ts);
+        }
+    }
+}
+
+/// Stateful `check_message` rejecting stale, future and replayed messages.
+///
+/// Messages are deduplicated by hash, which covers the session id and the nonce.
+pub struct MessageVerifier {
+    max_age: u64,
+    max_skew: u64,
+    cache_size: usize,
+    max_senders: usize,
+    schemes: Vec<HashScheme>,
+    clock: Arc<dyn Clock>,
+    senders: HashMap<Vec<u8>, SenderHistory>,
+    /// Newest timestamp of each forgotten sender, which must be past it to come back
+    /// since a forgotten sender looks unknown and could otherwise replay its messages.
+    forgotten: HashMap<Vec<u8>, u64>,
+    /// Floor of unknown senders absent from `forgotten`, raised when a floor is dropped
+    /// from it. Capped to the time of the drop so that senders dating messages ahead
+    /// of the clock cannot lock the others out.
+    evicted_floor: u64,
+}
+
+impl MessageVerifier {
+    pub fn new(args: &VerifierArgs, schemes: Vec<HashScheme>) -> Self {
+        Self::with_clock(args, schemes, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(
+        args: &VerifierArgs,
+        schemes: Vec<HashScheme>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            max_age: args.verify_max_age,
+            max_skew: args.verify_max_skew,
+            cache_size: args.verify_cache_size.max(1),
+            max_senders: args.verify_max_senders.max(1),
+            schemes,
+            clock,
+            senders: HashMap::new(),
+            forgotten: HashMap::new(),
+            evicted_floor: 0,
+        }
+    }
+
+    /// Verify an encoded `SignedMessage`, remembering it so that it is only accepted once.
+    pub fn verify(&mut self, data: &[u8]) -> Result<(SignedMessage, RawMessage, HashScheme)> {
+        let (msg, raw, scheme) = check_message_with(data, &self.schemes)?;
+        let now = self.clock.now();
+        let from = hex::encode(&raw.from_address);
+
+        ensure!(
+            raw.timestamp <= now.saturating_add(self.max_skew),
+            "Message from 0x{} is {}s in the future",
+            from,
+            raw.timestamp - now
+        );
+        let oldest = now.saturating_sub(self.max_age);
+        ensure!(
+            raw.timestamp >= oldest,
+            "Message from 0x{} is stale: {}s old",
+            from,
+            now - raw.timestamp
+        );
+
+        let history = self.senders.get(&raw.from_address);
+        if let Some(history) = history {
+            ensure!(
+                !history.hashes.contains(&msg.hash),
+                "Replayed message from 0x{}: hash=0x{}",
+                from,
+                hex::encode(&msg.hash)
+            );
+            ensure!(
+                raw.timestamp > history.floor,
+                "Message from 0x{} is older than its replay cache: timestamp={} floor={}",
+                from,
+                raw.timestamp,
+                history.floor
+            );
+        } else {
+            let floor = match self.forgotten.get(&raw.from_address) {
+                Some(floor) => *floor,
+                None => self.evicted_floor,
+            };
+            ensure!(
+                raw.timestamp > floor,
+                "Message from 0x{} is older than the replay cache: timestamp={} floor={}",
+                from,
+                raw.timestamp,
+                floor
+            );
+            if self.senders.len() >= self.max_senders {
+                self.forget_oldest_sender(now);
+            }
+        }
+
+        let history = self
+            .senders
+            .entry(raw.from_address.clone())
+            .or_insert_with(|| SenderHistory {
+                floor: self.forgotten.remove(&raw.from_address).unwrap_or_default(),
+                ..Default::default()
+            });
+        history.prune(oldest);
+        history.insert(raw.timestamp, msg.hash.clone(), self.cache_size);
+        history.last_seen = now;
+        Ok((msg, raw, scheme))
+    }
+
+    fn forget_oldest_sender(&mut self, now: u64) {
+        let oldest = self
+            .senders
+            .iter()
+            .min_by_key(|(_, h)| h.last_seen)
+            .map(|(addr, _)| addr.clone());
+        if let Some(addr) = oldest {
+            debug!(
+                "Replay cache full, forgetting sender 0x{}",
+                hex::encode(&addr)
+            );
+            if let Some(history) = self.senders.remove(&addr) {
+                self.forgotten.insert(addr, history.newest());
+            }
+        }
+        if self.forgotten.len() > self.max_senders {
+            // Floors past the time window protect nothing, the window rejects older ones.
+            let oldest = now.saturating_sub(self.max_age);
+            self.forgotten.retain(|_, floor| *floor >= oldest);
+        }
+        while self.forgotten.len() > self.max_senders {
+            let lowest = self
+                .forgotten
+                .iter()
+                .min_by_key(|(_, floor)| **floor)
+                .map(|(addr, floor)| (addr.clone(), *floor));
+            let Some((addr, floor)) = lowest else {
+                break;
+            };
+            self.forgotten.remove(&addr);
+            self.evicted_floor = self.evicted_floor.max(floor.min(now));
+        }
+    }
+
+    /// Number of senders currently tracked.
+    pub fn senders(&self) -> usize {
+        self.senders.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SteppedClock;
+    use rand::rngs::OsRng;
+
+    static NOW: u64 = 1700000000;
+
+    fn verifier(max_senders: usize) -> MessageVerifier {
+        let args = VerifierArgs {
+            verify_max_age: 100,
+            verify_max_skew: 10,
+            verify_cache_size: 8,
+            verify_max_senders: max_senders,
+        };
+        let clock = Arc::new(SteppedClock::new(NOW));
+        MessageVerifier::with_clock(&args, vec![CURRENT_HASH_SCHEME], clock)
+    }
+
+    async fn message(key: &SigningKey, timestamp: u64) -> Vec<u8> {
+        let (signed, _) = key
+            .create_message_with(
+                vec![0u8; 16],
+                MessageChannel::Normal(0),
+                b"test".to_vec(),
+                None,
+                None,
+                MessageOptions {
+                    nonce: None,
+                    rng: &mut OsRng,
+                    clock: &SteppedClock::new(timestamp),
+                    hash_scheme: CURRENT_HASH_SCHEME,
+                },
+            )
+            .await
+            .unwrap();
+        borsh::to_vec(&signed).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_replays() {
+        let key = random_signing_key();
+        let mut v = verifier(10);
+        let data = message(&key, NOW).await;
+        v.verify(&data).unwrap();
+        assert!(v.verify(&data).is_err());
+        v.verify(&message(&key, NOW + 1).await).unwrap();
+    }
+
+    #[tokio::test]
+    async fn enforces_time_window() {
+        let key = random_signing_key();
+        let mut v = verifier(10);
+        v.verify(&message(&key, NOW - 100).await).unwrap();
+        assert!(v.verify(&message(&key, NOW - 101).await).is_err());
+        v.verify(&message(&key, NOW + 10).await).unwrap();
+        assert!(v.verify(&message(&key, NOW + 11).await).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_replays_past_cache_size() {
+        let key = random_signing_key();
+        let mut v = verifier(10);
+        let first = message(&key, NOW - 50).await;
+        v.verify(&first).unwrap();
+        for i in 0..8 {
+            v.verify(&message(&key, NOW - 40 + i).await).unwrap();
+        }
+        assert!(v.verify(&first).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_replays_of_evicted_senders() {
+        let victim = random_signing_key();
+        let mut v = verifier(1);
+        let data = message(&victim, NOW - 5).await;
+        v.verify(&data).unwrap();
+
+        // Pushes the victim out of the cache
+        v.verify(&message(&random_signing_key(), NOW).await)
+            .unwrap();
+        assert_eq!(v.senders(), 1);
+        assert!(v.verify(&data).is_err());
+
+        // Newer messages of the victim still go through
+        v.verify(&message(&victim, NOW + 1).await).unwrap();
+    }
+
+    #[tokio::test]
+    async fn future_dated_senders_do_not_lock_out_others() {
+        let mut v = verifier(1);
+        let early = random_signing_key();
+        let data = message(&early, NOW + 10).await;
+        v.verify(&data).unwrap();
+
+        // Pushes the future-dated sender out of the cache
+        v.verify(&message(&random_signing_key(), NOW).await)
+            .unwrap();
+        assert!(v.verify(&data).is_err());
+        assert!(v.verify(&message(&early, NOW + 5).await).is_err());
+
+        // Other senders are held neither to its floor nor to the floors dropped for room
+        v.verify(&message(&random_signing_key(), NOW).await)
+            .unwrap();
+        v.verify(&message(&random_signing_key(), NOW + 1).await)
+            .unwrap();
+    }
+}